@@ -105,6 +105,8 @@ fn main() -> ! {
         ..Default::default()
     });
     let res = wifi_interface.set_configuration(&client_config);
+    println!("set_configuration returned {:?}", res);
+    let res = wifi_interface.connect();
     println!("wifi_connect returned {:?}", res);
 
     println!("{:?}", wifi_interface.get_capabilities());
@@ -162,8 +164,8 @@ fn main() -> ! {
 
     println!("Start busy loop on main");
 
-    let mut network = Network::new(wifi_interface, current_millis);
-    let mut socket = network.get_socket();
+    let network = Network::new(wifi_interface, current_millis);
+    let mut socket = network.get_socket().unwrap();
 
     loop {
         println!("Making HTTP request");
@@ -100,6 +100,8 @@ fn main() -> ! {
         ..Default::default()
     });
     let res = wifi_interface.set_configuration(&client_config);
+    println!("set_configuration returned {:?}", res);
+    let res = wifi_interface.connect();
     println!("wifi_connect returned {:?}", res);
 
     println!("{:?}", wifi_interface.get_capabilities());
@@ -136,16 +138,18 @@ fn main() -> ! {
 
     println!("Start busy loop on main");
 
-    let mut network = Network::new(wifi_interface, current_millis);
-    let mut socket = network.get_socket();
+    let network = Network::new(wifi_interface, current_millis);
+    let address = network.resolve("www.mobile-j.de").unwrap();
+    println!("www.mobile-j.de resolved to {:?}", address);
+    let address = Ipv4Address::from_bytes(&address.octets());
+
+    let mut socket = network.get_socket().unwrap();
 
     loop {
         println!("Making HTTP request");
         socket.work();
 
-        socket
-            .open(Ipv4Address::new(142, 250, 185, 115), 80)
-            .unwrap();
+        socket.open(address, 80).unwrap();
 
         socket
             .write(b"GET / HTTP/1.0\r\nHost: www.mobile-j.de\r\n\r\n")
@@ -0,0 +1,188 @@
+#![no_std]
+#![no_main]
+#![feature(c_variadic)]
+#![feature(const_mut_refs)]
+
+#[cfg(feature = "esp32")]
+use esp32_hal as hal;
+#[cfg(feature = "esp32c3")]
+use esp32c3_hal as hal;
+#[cfg(feature = "esp32s2")]
+use esp32s2_hal as hal;
+#[cfg(feature = "esp32s3")]
+use esp32s3_hal as hal;
+
+use embedded_io::blocking::*;
+use embedded_svc::wifi::{
+    AccessPointInfo, ClientConfiguration, ClientConnectionStatus, ClientIpStatus, ClientStatus,
+    Configuration, Status, Wifi,
+};
+
+use esp_backtrace as _;
+use esp_println::logger::init_logger;
+use esp_println::{print, println};
+use esp_wifi::wifi::utils::create_network_interface;
+use esp_wifi::wifi_interface::{timestamp, Network, WifiError};
+use esp_wifi::{create_network_stack_storage, network_stack_storage};
+use esp_wifi::{current_millis, initialize};
+use hal::clock::{ClockControl, CpuClock};
+use hal::{pac::Peripherals, prelude::*, Rtc};
+
+#[cfg(any(feature = "esp32c3"))]
+use hal::system::SystemExt;
+
+#[cfg(feature = "esp32c3")]
+use riscv_rt::entry;
+#[cfg(any(feature = "esp32", feature = "esp32s3", feature = "esp32s2"))]
+use xtensa_lx_rt::entry;
+
+const SSID: &str = env!("SSID");
+const PASSWORD: &str = env!("PASSWORD");
+
+#[entry]
+fn main() -> ! {
+    init_logger(log::LevelFilter::Info);
+    esp_wifi::init_heap();
+
+    let peripherals = Peripherals::take().unwrap();
+
+    #[cfg(not(feature = "esp32"))]
+    let system = peripherals.SYSTEM.split();
+    #[cfg(feature = "esp32")]
+    let system = peripherals.DPORT.split();
+
+    #[cfg(feature = "esp32c3")]
+    let clocks = ClockControl::configure(system.clock_control, CpuClock::Clock160MHz).freeze();
+    #[cfg(any(feature = "esp32", feature = "esp32s3", feature = "esp32s2"))]
+    let clocks = ClockControl::configure(system.clock_control, CpuClock::Clock240MHz).freeze();
+
+    let mut rtc = Rtc::new(peripherals.RTC_CNTL);
+
+    // Disable watchdog timers
+    #[cfg(not(any(feature = "esp32", feature = "esp32s2")))]
+    rtc.swd.disable();
+
+    rtc.rwdt.disable();
+
+    let mut storage = create_network_stack_storage!(3, 8, 1);
+    let ethernet = create_network_interface(network_stack_storage!(storage));
+    let mut wifi_interface = esp_wifi::wifi_interface::Wifi::new(ethernet);
+
+    #[cfg(feature = "esp32c3")]
+    {
+        use hal::systimer::SystemTimer;
+        let syst = SystemTimer::new(peripherals.SYSTIMER);
+        initialize(syst.alarm0, peripherals.RNG, &clocks).unwrap();
+    }
+    #[cfg(any(feature = "esp32", feature = "esp32s3", feature = "esp32s2"))]
+    {
+        use hal::timer::TimerGroup;
+        let timg1 = TimerGroup::new(peripherals.TIMG1, &clocks);
+        initialize(timg1.timer0, peripherals.RNG, &clocks).unwrap();
+    }
+
+    println!("{:?}", wifi_interface.get_status());
+
+    println!("Start Wifi Scan");
+    let res: Result<(heapless::Vec<AccessPointInfo, 10>, usize), WifiError> =
+        wifi_interface.scan_n();
+    if let Ok((res, _count)) = res {
+        for ap in res {
+            println!("{:?}", ap);
+        }
+    }
+
+    println!("Call wifi_connect");
+    let client_config = Configuration::Client(ClientConfiguration {
+        ssid: SSID.into(),
+        password: PASSWORD.into(),
+        ..Default::default()
+    });
+    let res = wifi_interface.set_configuration(&client_config);
+    println!("set_configuration returned {:?}", res);
+    let res = wifi_interface.connect();
+    println!("wifi_connect returned {:?}", res);
+
+    println!("{:?}", wifi_interface.get_capabilities());
+    println!("{:?}", wifi_interface.get_status());
+
+    // wait to get connected
+    println!("Wait to get connected");
+    loop {
+        if let Status(ClientStatus::Started(_), _) = wifi_interface.get_status() {
+            break;
+        }
+    }
+    println!("{:?}", wifi_interface.get_status());
+
+    // wait for getting an ip address
+    println!("Wait to get an ip address");
+    loop {
+        wifi_interface.poll_dhcp().unwrap();
+
+        wifi_interface
+            .network_interface()
+            .poll(timestamp())
+            .unwrap();
+
+        if let Status(
+            ClientStatus::Started(ClientConnectionStatus::Connected(ClientIpStatus::Done(config))),
+            _,
+        ) = wifi_interface.get_status()
+        {
+            println!("got ip {:?}", config);
+            break;
+        }
+    }
+
+    println!("Start busy loop on main");
+
+    let network = Network::new(wifi_interface, current_millis);
+    let mut socket = network.get_socket().unwrap();
+
+    loop {
+        socket.listen(8080).unwrap();
+        println!("Listening on port 8080");
+
+        let remote = socket.accept().unwrap();
+        println!("Connection from {}", remote);
+
+        let mut buffer = [0u8; 1024];
+        let mut pos = 0;
+        let wait_end = current_millis() + 2 * 1000;
+        loop {
+            if let Ok(len) = socket.read(&mut buffer[pos..]) {
+                pos += len;
+            } else {
+                break;
+            }
+
+            let request = unsafe { core::str::from_utf8_unchecked(&buffer[..pos]) };
+            if request.contains("\r\n\r\n") || pos == buffer.len() {
+                print!("{}", request);
+                break;
+            }
+
+            if current_millis() > wait_end {
+                println!("Timeout");
+                break;
+            }
+        }
+
+        socket
+            .write_all(
+                b"HTTP/1.0 200 OK\r\nContent-Type: text/html\r\n\r\n\
+                <html><body><h1>Hello Rust! Hello esp-wifi!</h1></body></html>\r\n",
+            )
+            .ok();
+        socket.flush().ok();
+
+        // give the response some time to get out before the connection is aborted
+        let wait_end = current_millis() + 1000;
+        while current_millis() < wait_end {
+            socket.work();
+        }
+
+        socket.disconnect();
+    }
+}
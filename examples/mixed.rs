@@ -0,0 +1,131 @@
+#![no_std]
+#![no_main]
+#![feature(c_variadic)]
+#![feature(const_mut_refs)]
+
+#[cfg(feature = "esp32")]
+use esp32_hal as hal;
+#[cfg(feature = "esp32c3")]
+use esp32c3_hal as hal;
+#[cfg(feature = "esp32s2")]
+use esp32s2_hal as hal;
+#[cfg(feature = "esp32s3")]
+use esp32s3_hal as hal;
+
+use embedded_svc::wifi::{
+    AccessPointConfiguration, ApStatus, ClientConfiguration, ClientConnectionStatus,
+    ClientIpStatus, ClientStatus, Configuration, Status, Wifi,
+};
+
+use esp_backtrace as _;
+use esp_println::logger::init_logger;
+use esp_println::println;
+use esp_wifi::initialize;
+use esp_wifi::wifi::utils::create_network_interface;
+use esp_wifi::wifi_interface::timestamp;
+use esp_wifi::{create_network_stack_storage, network_stack_storage};
+use hal::clock::{ClockControl, CpuClock};
+use hal::{pac::Peripherals, prelude::*, Rtc};
+
+#[cfg(any(feature = "esp32c3"))]
+use hal::system::SystemExt;
+
+#[cfg(feature = "esp32c3")]
+use riscv_rt::entry;
+#[cfg(any(feature = "esp32", feature = "esp32s3", feature = "esp32s2"))]
+use xtensa_lx_rt::entry;
+
+const SSID: &str = env!("SSID");
+const PASSWORD: &str = env!("PASSWORD");
+
+#[entry]
+fn main() -> ! {
+    init_logger(log::LevelFilter::Info);
+    esp_wifi::init_heap();
+
+    let peripherals = Peripherals::take().unwrap();
+
+    #[cfg(not(feature = "esp32"))]
+    let system = peripherals.SYSTEM.split();
+    #[cfg(feature = "esp32")]
+    let system = peripherals.DPORT.split();
+
+    #[cfg(feature = "esp32c3")]
+    let clocks = ClockControl::configure(system.clock_control, CpuClock::Clock160MHz).freeze();
+    #[cfg(any(feature = "esp32", feature = "esp32s3", feature = "esp32s2"))]
+    let clocks = ClockControl::configure(system.clock_control, CpuClock::Clock240MHz).freeze();
+
+    let mut rtc = Rtc::new(peripherals.RTC_CNTL);
+
+    // Disable watchdog timers
+    #[cfg(not(any(feature = "esp32", feature = "esp32s2")))]
+    rtc.swd.disable();
+
+    rtc.rwdt.disable();
+
+    let mut storage = create_network_stack_storage!(3, 8, 1);
+    let ethernet = create_network_interface(network_stack_storage!(storage));
+    let mut wifi_interface = esp_wifi::wifi_interface::Wifi::new(ethernet);
+
+    #[cfg(feature = "esp32c3")]
+    {
+        use hal::systimer::SystemTimer;
+        let syst = SystemTimer::new(peripherals.SYSTIMER);
+        initialize(syst.alarm0, peripherals.RNG, &clocks).unwrap();
+    }
+    #[cfg(any(feature = "esp32", feature = "esp32s3", feature = "esp32s2"))]
+    {
+        use hal::timer::TimerGroup;
+        let timg1 = TimerGroup::new(peripherals.TIMG1, &clocks);
+        initialize(timg1.timer0, peripherals.RNG, &clocks).unwrap();
+    }
+
+    println!("{:?}", wifi_interface.get_capabilities());
+
+    println!("Start access point and connect");
+    let mixed_config = Configuration::Mixed(
+        ClientConfiguration {
+            ssid: SSID.into(),
+            password: PASSWORD.into(),
+            ..Default::default()
+        },
+        AccessPointConfiguration {
+            ssid: "esp-wifi".into(),
+            max_connections: 4,
+            ..Default::default()
+        },
+    );
+    let res = wifi_interface.set_configuration(&mixed_config);
+    println!("set_configuration returned {:?}", res);
+    let res = wifi_interface.connect();
+    println!("wifi_connect returned {:?}", res);
+
+    // wait for getting an ip address on the station interface while the access point is up
+    println!("Wait to get an ip address");
+    loop {
+        wifi_interface.poll_dhcp().unwrap();
+
+        wifi_interface
+            .network_interface()
+            .poll(timestamp())
+            .unwrap();
+
+        if let Status(
+            ClientStatus::Started(ClientConnectionStatus::Connected(ClientIpStatus::Done(config))),
+            ApStatus::Started(_),
+        ) = wifi_interface.get_status()
+        {
+            println!("got ip {:?}", config);
+            break;
+        }
+    }
+
+    println!("{:?}", wifi_interface.get_status());
+    println!("Start busy loop on main");
+
+    loop {
+        wifi_interface.poll_dhcp().unwrap();
+
+        wifi_interface.network_interface().poll(timestamp()).ok();
+    }
+}
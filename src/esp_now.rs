@@ -0,0 +1,192 @@
+//! ESP-NOW connectionless messaging
+//!
+//! ESP-NOW shares the radio with the normal Wi-Fi operation. Peers need to be on the same
+//! channel as the station (or soft-AP) the device is currently using.
+
+use core::cell::RefCell;
+
+use critical_section::Mutex;
+
+use crate::binary::c_types::{c_int, c_void};
+use crate::binary::include::{
+    esp_err_t, wifi_interface_t, wifi_interface_t_WIFI_IF_AP, wifi_interface_t_WIFI_IF_STA,
+};
+use crate::current_millis;
+use crate::wifi_interface::Wifi;
+
+/// Maximum length of the payload of a single ESP-NOW frame.
+pub const ESP_NOW_MAX_DATA_LEN: usize = 250;
+
+const SEND_TIMEOUT_MS: u64 = 1000;
+
+#[repr(C)]
+#[allow(non_camel_case_types)]
+struct esp_now_peer_info_t {
+    peer_addr: [u8; 6],
+    lmk: [u8; 16],
+    channel: u8,
+    ifidx: wifi_interface_t,
+    encrypt: bool,
+    priv_: *mut c_void,
+}
+
+extern "C" {
+    fn esp_now_init() -> esp_err_t;
+    fn esp_now_deinit() -> esp_err_t;
+    fn esp_now_register_recv_cb(
+        cb: Option<unsafe extern "C" fn(mac_addr: *const u8, data: *const u8, data_len: c_int)>,
+    ) -> esp_err_t;
+    fn esp_now_register_send_cb(
+        cb: Option<unsafe extern "C" fn(mac_addr: *const u8, status: u32)>,
+    ) -> esp_err_t;
+    fn esp_now_add_peer(peer: *const esp_now_peer_info_t) -> esp_err_t;
+    fn esp_now_del_peer(peer_addr: *const u8) -> esp_err_t;
+    fn esp_now_send(peer_addr: *const u8, data: *const u8, len: usize) -> esp_err_t;
+}
+
+/// Result of the last send reported by the driver: `Some(true)` on success
+static SEND_STATUS: Mutex<RefCell<Option<bool>>> = Mutex::new(RefCell::new(None));
+
+static RECEIVE_CB: Mutex<RefCell<Option<fn(&[u8; 6], &[u8])>>> = Mutex::new(RefCell::new(None));
+
+#[derive(Debug, Clone, Copy)]
+pub enum EspNowError {
+    Unknown(i32),
+    /// The payload is empty or longer than [ESP_NOW_MAX_DATA_LEN]
+    InvalidArgument,
+    /// The peer didn't acknowledge the frame
+    SendFailed,
+    /// The driver didn't report the send status in time
+    Timeout,
+}
+
+/// ESP-NOW on top of a running [Wifi] instance
+///
+/// Creating it only requires the Wi-Fi driver to be started, the [Wifi] instance stays usable
+/// for station operation. Dropping it de-initializes ESP-NOW again.
+pub struct EspNow {
+    _private: (),
+}
+
+impl EspNow {
+    pub fn new(_wifi: &mut Wifi) -> Result<EspNow, EspNowError> {
+        let res = unsafe { esp_now_init() };
+        if res != 0 {
+            return Err(EspNowError::Unknown(res));
+        }
+
+        let res = unsafe { esp_now_register_send_cb(Some(send_cb)) };
+        if res != 0 {
+            unsafe { esp_now_deinit() };
+            return Err(EspNowError::Unknown(res));
+        }
+
+        let res = unsafe { esp_now_register_recv_cb(Some(recv_cb)) };
+        if res != 0 {
+            unsafe { esp_now_deinit() };
+            return Err(EspNowError::Unknown(res));
+        }
+
+        Ok(EspNow { _private: () })
+    }
+
+    /// Add an unencrypted peer
+    ///
+    /// A channel of 0 means the peer uses whatever channel the device is currently on.
+    pub fn add_peer(&mut self, mac: [u8; 6], channel: u8) -> Result<(), EspNowError> {
+        let ifidx = if crate::wifi::is_ap_data_interface() {
+            wifi_interface_t_WIFI_IF_AP
+        } else {
+            wifi_interface_t_WIFI_IF_STA
+        };
+
+        let peer = esp_now_peer_info_t {
+            peer_addr: mac,
+            lmk: [0u8; 16],
+            channel,
+            ifidx,
+            encrypt: false,
+            priv_: core::ptr::null_mut(),
+        };
+
+        let res = unsafe { esp_now_add_peer(&peer) };
+        if res != 0 {
+            return Err(EspNowError::Unknown(res));
+        }
+
+        Ok(())
+    }
+
+    pub fn remove_peer(&mut self, mac: [u8; 6]) -> Result<(), EspNowError> {
+        let res = unsafe { esp_now_del_peer(mac.as_ptr()) };
+        if res != 0 {
+            return Err(EspNowError::Unknown(res));
+        }
+
+        Ok(())
+    }
+
+    /// Send data to a peer previously added via [EspNow::add_peer]
+    ///
+    /// This blocks until the driver reports whether the peer acknowledged the frame.
+    pub fn send(&mut self, mac: [u8; 6], data: &[u8]) -> Result<(), EspNowError> {
+        if data.is_empty() || data.len() > ESP_NOW_MAX_DATA_LEN {
+            return Err(EspNowError::InvalidArgument);
+        }
+
+        critical_section::with(|cs| SEND_STATUS.borrow_ref_mut(cs).take());
+
+        let res = unsafe { esp_now_send(mac.as_ptr(), data.as_ptr(), data.len()) };
+        if res != 0 {
+            return Err(EspNowError::Unknown(res));
+        }
+
+        let started = current_millis();
+        loop {
+            match critical_section::with(|cs| SEND_STATUS.borrow_ref_mut(cs).take()) {
+                Some(true) => return Ok(()),
+                Some(false) => return Err(EspNowError::SendFailed),
+                None => (),
+            }
+
+            if current_millis() - started > SEND_TIMEOUT_MS {
+                return Err(EspNowError::Timeout);
+            }
+        }
+    }
+
+    /// Set the function called for every received frame with the sender's MAC address and the payload
+    ///
+    /// The callback runs in the context of the Wi-Fi task so it should return quickly.
+    pub fn set_receive_callback(&mut self, cb: Option<fn(&[u8; 6], &[u8])>) {
+        critical_section::with(|cs| *RECEIVE_CB.borrow_ref_mut(cs) = cb);
+    }
+}
+
+impl Drop for EspNow {
+    fn drop(&mut self) {
+        critical_section::with(|cs| *RECEIVE_CB.borrow_ref_mut(cs) = None);
+        unsafe {
+            esp_now_deinit();
+        }
+    }
+}
+
+unsafe extern "C" fn send_cb(_mac_addr: *const u8, status: u32) {
+    // ESP_NOW_SEND_SUCCESS = 0, ESP_NOW_SEND_FAIL = 1
+    critical_section::with(|cs| *SEND_STATUS.borrow_ref_mut(cs) = Some(status == 0));
+}
+
+unsafe extern "C" fn recv_cb(mac_addr: *const u8, data: *const u8, data_len: c_int) {
+    let cb = critical_section::with(|cs| *RECEIVE_CB.borrow_ref(cs));
+
+    if let Some(cb) = cb {
+        if mac_addr.is_null() || data.is_null() || data_len <= 0 {
+            return;
+        }
+
+        let mac = &*(mac_addr as *const [u8; 6]);
+        let data = core::slice::from_raw_parts(data, data_len as usize);
+        cb(mac, data);
+    }
+}
@@ -2,6 +2,10 @@
 #![cfg_attr(target_arch = "xtensa", feature(asm_experimental_arch))]
 #![feature(c_variadic)]
 #![feature(layout_for_ptr)]
+#![cfg_attr(
+    feature = "async",
+    feature(generic_associated_types, type_alias_impl_trait)
+)]
 
 use core::cell::RefCell;
 use core::mem::MaybeUninit;
@@ -61,6 +65,9 @@ use timer::{get_systimer_count, TICKS_PER_SECOND};
 #[cfg(all(feature = "embedded-svc", feature = "wifi"))]
 pub mod wifi_interface;
 
+#[cfg(all(feature = "embedded-svc", feature = "wifi"))]
+pub mod esp_now;
+
 #[cfg(feature = "esp32c3")]
 use esp32c3_hal::interrupt;
 
@@ -68,6 +75,19 @@ pub fn current_millis() -> u64 {
     get_systimer_count() / (TICKS_PER_SECOND / 1000)
 }
 
+/// Halt the CPU until the next interrupt, at the latest the next tick of the scheduler.
+pub(crate) fn wait_for_interrupt() {
+    #[cfg(feature = "esp32c3")]
+    unsafe {
+        riscv::asm::wfi();
+    }
+
+    #[cfg(any(feature = "esp32", feature = "esp32s3", feature = "esp32s2"))]
+    unsafe {
+        core::arch::asm!("waiti 0");
+    }
+}
+
 #[cfg(not(coex))]
 const HEAP_SIZE: usize = 64 * 1024;
 
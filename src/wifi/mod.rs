@@ -1,7 +1,7 @@
 #[doc(hidden)]
 pub mod os_adapter;
 
-use core::{cell::RefCell, marker::PhantomData};
+use core::cell::RefCell;
 
 use crate::common_adapter::*;
 
@@ -27,22 +27,23 @@ pub mod utils;
 #[cfg(coex)]
 use crate::binary::include::{coex_adapter_funcs_t, coex_pre_init, esp_coex_adapter_register};
 
-use crate::{
-    binary::include::{
-        __BindgenBitfieldUnit, esp_err_t, esp_interface_t_ESP_IF_WIFI_STA, esp_supplicant_init,
-        esp_wifi_connect, esp_wifi_init_internal, esp_wifi_internal_free_rx_buffer,
-        esp_wifi_internal_reg_rxcb, esp_wifi_internal_tx, esp_wifi_scan_start, esp_wifi_set_config,
-        esp_wifi_set_country, esp_wifi_set_mode, esp_wifi_set_ps, esp_wifi_set_tx_done_cb,
-        esp_wifi_start, esp_wifi_stop, g_wifi_default_wpa_crypto_funcs, wifi_active_scan_time_t,
-        wifi_auth_mode_t_WIFI_AUTH_OPEN, wifi_config_t,
-        wifi_country_policy_t_WIFI_COUNTRY_POLICY_MANUAL, wifi_country_t, wifi_init_config_t,
-        wifi_interface_t_WIFI_IF_STA, wifi_mode_t_WIFI_MODE_STA, wifi_osi_funcs_t,
-        wifi_pmf_config_t, wifi_scan_config_t, wifi_scan_method_t_WIFI_FAST_SCAN,
-        wifi_scan_threshold_t, wifi_scan_time_t, wifi_scan_type_t_WIFI_SCAN_TYPE_ACTIVE,
-        wifi_sort_method_t_WIFI_CONNECT_AP_BY_SIGNAL, wifi_sta_config_t, wpa_crypto_funcs_t,
-        ESP_WIFI_OS_ADAPTER_MAGIC, ESP_WIFI_OS_ADAPTER_VERSION, WIFI_INIT_CONFIG_MAGIC,
-    },
-    compat::queue::SimpleQueue,
+use crate::binary::include::{
+    __BindgenBitfieldUnit, esp_err_t, esp_interface_t_ESP_IF_WIFI_AP,
+    esp_interface_t_ESP_IF_WIFI_STA, esp_supplicant_init, esp_wifi_connect, esp_wifi_get_ps,
+    esp_wifi_init_internal, esp_wifi_internal_free_rx_buffer, esp_wifi_internal_reg_rxcb,
+    esp_wifi_internal_tx, esp_wifi_scan_start, esp_wifi_set_config, esp_wifi_set_country,
+    esp_wifi_set_mode, esp_wifi_set_ps, esp_wifi_set_tx_done_cb, esp_wifi_start, esp_wifi_stop,
+    g_wifi_default_wpa_crypto_funcs, wifi_active_scan_time_t, wifi_ap_config_t, wifi_auth_mode_t,
+    wifi_auth_mode_t_WIFI_AUTH_OPEN, wifi_bandwidth_t, wifi_bandwidth_t_WIFI_BW_HT40,
+    wifi_config_t, wifi_country_policy_t_WIFI_COUNTRY_POLICY_MANUAL, wifi_country_t,
+    wifi_init_config_t, wifi_interface_t, wifi_interface_t_WIFI_IF_AP,
+    wifi_interface_t_WIFI_IF_STA, wifi_mode_t, wifi_mode_t_WIFI_MODE_AP, wifi_mode_t_WIFI_MODE_STA,
+    wifi_osi_funcs_t, wifi_pmf_config_t, wifi_ps_type_t, wifi_ps_type_t_WIFI_PS_MAX_MODEM,
+    wifi_ps_type_t_WIFI_PS_MIN_MODEM, wifi_ps_type_t_WIFI_PS_NONE, wifi_scan_config_t,
+    wifi_scan_method_t_WIFI_FAST_SCAN, wifi_scan_threshold_t, wifi_scan_time_t,
+    wifi_scan_type_t_WIFI_SCAN_TYPE_ACTIVE, wifi_sort_method_t_WIFI_CONNECT_AP_BY_SIGNAL,
+    wifi_sta_config_t, wpa_crypto_funcs_t, ESP_WIFI_OS_ADAPTER_MAGIC, ESP_WIFI_OS_ADAPTER_VERSION,
+    WIFI_INIT_CONFIG_MAGIC,
 };
 use log::{debug, info};
 
@@ -51,36 +52,139 @@ static DUMP_PACKETS: bool = true;
 #[cfg(not(feature = "dump_packets"))]
 static DUMP_PACKETS: bool = false;
 
+/// Storage for one ethernet frame in the receive or transmit queue, see `WifiDevice::set_queues`.
 #[derive(Debug, Clone, Copy)]
-pub(crate) struct DataFrame<'a> {
+pub struct DataFrame {
     len: usize,
     data: [u8; 1536],
-    _phantom: PhantomData<&'a ()>,
 }
 
-impl<'a> DataFrame<'a> {
-    pub(crate) fn new() -> DataFrame<'a> {
+impl DataFrame {
+    pub const fn new() -> DataFrame {
         DataFrame {
             len: 0,
             data: [0u8; 1536],
-            _phantom: Default::default(),
         }
     }
+}
+
+/// Number of frames the receive queue holds unless `WifiDevice::set_queues` is used.
+pub const DEFAULT_RX_QUEUE_SIZE: usize = 3;
+
+/// Number of frames the transmit queue holds unless `WifiDevice::set_queues` is used.
+pub const DEFAULT_TX_QUEUE_SIZE: usize = 1;
+
+/// A FIFO of frames in storage given by the application.
+pub(crate) struct FrameQueue {
+    frames: Option<&'static mut [DataFrame]>,
+    read_index: usize,
+    len: usize,
+}
+
+impl FrameQueue {
+    const fn new() -> FrameQueue {
+        FrameQueue {
+            frames: None,
+            read_index: 0,
+            len: 0,
+        }
+    }
+
+    fn set_storage(&mut self, frames: &'static mut [DataFrame]) {
+        self.frames = Some(frames);
+        self.clear();
+    }
+
+    fn capacity(&self) -> usize {
+        self.frames.as_ref().map_or(0, |frames| frames.len())
+    }
 
-    pub(crate) fn from_bytes(bytes: &[u8]) -> DataFrame {
-        let mut data = DataFrame::new();
-        data.len = bytes.len();
-        data.data[..bytes.len()].copy_from_slice(bytes);
-        data
+    pub(crate) fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub(crate) fn is_full(&self) -> bool {
+        self.len >= self.capacity()
+    }
+
+    fn clear(&mut self) {
+        self.read_index = 0;
+        self.len = 0;
+    }
+
+    /// Get the free frame at the end of the queue, `push` adds it to the queue.
+    fn next_free(&mut self) -> Option<&mut DataFrame> {
+        if self.is_full() {
+            return None;
+        }
+
+        let capacity = self.capacity();
+        let index = (self.read_index + self.len) % capacity;
+        self.frames.as_mut().map(|frames| &mut frames[index])
+    }
+
+    fn push(&mut self) {
+        self.len += 1;
+    }
+
+    fn enqueue(&mut self, bytes: &[u8]) -> bool {
+        match self.next_free() {
+            Some(frame) => {
+                frame.len = bytes.len();
+                frame.data[..bytes.len()].copy_from_slice(bytes);
+                self.push();
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn dequeue(&mut self) -> Option<DataFrame> {
+        if self.is_empty() {
+            return None;
+        }
+
+        let frame = self.frames.as_ref()?[self.read_index];
+        self.read_index = (self.read_index + 1) % self.capacity();
+        self.len -= 1;
+        Some(frame)
     }
 }
 
-pub(crate) static DATA_QUEUE_RX: Mutex<RefCell<SimpleQueue<DataFrame, 3>>> =
-    Mutex::new(RefCell::new(SimpleQueue::new()));
+pub(crate) static DATA_QUEUE_RX: Mutex<RefCell<FrameQueue>> =
+    Mutex::new(RefCell::new(FrameQueue::new()));
+
+static DATA_QUEUE_TX: Mutex<RefCell<FrameQueue>> = Mutex::new(RefCell::new(FrameQueue::new()));
+
+/// Lease time in seconds and the time in milliseconds the last DHCP ACK for this station was received.
+/// smoltcp doesn't expose the lease time so it's picked from the received frames.
+pub(crate) static DHCP_LEASE: Mutex<RefCell<Option<(u32, u64)>>> = Mutex::new(RefCell::new(None));
+
+/// Time in milliseconds the station got connected to an AP the last time.
+pub(crate) static STA_CONNECTED_AT: Mutex<RefCell<Option<u64>>> = Mutex::new(RefCell::new(None));
+
+/// Frame counters of the data path, see `WifiDevice::stats`
+static DEVICE_STATS: Mutex<RefCell<DeviceStats>> = Mutex::new(RefCell::new(DeviceStats::new()));
+
+/// Hostname added to outgoing DHCP messages
+pub(crate) static DHCP_HOSTNAME: Mutex<RefCell<Option<heapless::String<32>>>> =
+    Mutex::new(RefCell::new(None));
+
+/// The weakest authentication method the station accepts from an AP
+static mut STA_MIN_AUTH: wifi_auth_mode_t = wifi_auth_mode_t_WIFI_AUTH_OPEN;
 
-pub(crate) static mut TX_BUFFER: [u8; 1536] = [0u8; 1536]; // should be a queue
-pub(crate) static mut TX_QUEUED: bool = false;
-pub(crate) static mut TX_QUEUED_DATA_LEN: u16 = 0;
+/// The number of beacon intervals the station sleeps in max modem power save mode
+static mut STA_LISTEN_INTERVAL: u16 = 3;
+
+/// The power save mode set via `set_power_save_mode`, `wifi_start` applies it again
+static mut POWER_SAVE_MODE: Option<PowerSaveMode> = None;
+
+/// The country information set via `set_country`, `wifi_start` applies it again
+static mut COUNTRY: Option<wifi_country_t> = None;
+
+/// The interface the network stack sends its frames on, the soft-AP interface
+/// is only used if the station interface isn't active.
+pub(crate) static mut DATA_INTERFACE: wifi_interface_t = wifi_interface_t_WIFI_IF_STA;
 
 #[derive(Debug, Clone, Copy)]
 pub enum WifiError {
@@ -88,6 +192,276 @@ pub enum WifiError {
     WrongClockConfig,
 }
 
+/// Power save mode of the WiFi modem
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowerSaveMode {
+    /// The radio is always on
+    None,
+    /// Modem sleep, the station wakes up every DTIM period
+    Minimum,
+    /// Modem sleep, the station wakes up every listen interval (see `Wifi::set_listen_interval`)
+    Maximum,
+}
+
+impl PowerSaveMode {
+    #[allow(non_upper_case_globals)]
+    pub(crate) fn from_raw(ps: wifi_ps_type_t) -> PowerSaveMode {
+        match ps {
+            wifi_ps_type_t_WIFI_PS_MIN_MODEM => PowerSaveMode::Minimum,
+            wifi_ps_type_t_WIFI_PS_MAX_MODEM => PowerSaveMode::Maximum,
+            _ => PowerSaveMode::None,
+        }
+    }
+
+    pub(crate) fn to_raw(self) -> wifi_ps_type_t {
+        match self {
+            PowerSaveMode::None => wifi_ps_type_t_WIFI_PS_NONE,
+            PowerSaveMode::Minimum => wifi_ps_type_t_WIFI_PS_MIN_MODEM,
+            PowerSaveMode::Maximum => wifi_ps_type_t_WIFI_PS_MAX_MODEM,
+        }
+    }
+}
+
+/// Channel bandwidth
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bandwidth {
+    /// 20 MHz
+    Ht20,
+    /// 40 MHz
+    Ht40,
+}
+
+impl Bandwidth {
+    #[allow(non_upper_case_globals)]
+    pub(crate) fn from_raw(bw: wifi_bandwidth_t) -> Bandwidth {
+        match bw {
+            wifi_bandwidth_t_WIFI_BW_HT40 => Bandwidth::Ht40,
+            _ => Bandwidth::Ht20,
+        }
+    }
+
+    pub(crate) fn to_raw(&self) -> wifi_bandwidth_t {
+        match self {
+            Bandwidth::Ht20 => crate::binary::include::wifi_bandwidth_t_WIFI_BW_HT20,
+            Bandwidth::Ht40 => wifi_bandwidth_t_WIFI_BW_HT40,
+        }
+    }
+}
+
+/// Which radio gets precedence when Wi-Fi and Bluetooth share the antenna
+#[cfg(coex)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CoexMode {
+    /// Share the time evenly
+    Balanced,
+    /// Prefer Wi-Fi traffic
+    WifiPriority,
+    /// Prefer Bluetooth LE traffic
+    BlePriority,
+}
+
+#[cfg(coex)]
+impl CoexMode {
+    pub(crate) fn to_raw(&self) -> crate::binary::include::coex_prefer_t {
+        match self {
+            CoexMode::Balanced => crate::binary::include::coex_prefer_t_COEX_PREFER_BALANCE,
+            CoexMode::WifiPriority => crate::binary::include::coex_prefer_t_COEX_PREFER_WIFI,
+            CoexMode::BlePriority => crate::binary::include::coex_prefer_t_COEX_PREFER_BT,
+        }
+    }
+}
+
+/// A copy of the driver's `wifi_ap_record_t` with all the information about a scanned AP.
+#[derive(Debug, Clone, Copy)]
+pub struct ApRecord {
+    pub bssid: [u8; 6],
+    /// SSID, NUL terminated
+    pub ssid: [u8; 33],
+    /// Primary channel
+    pub primary: u8,
+    /// Secondary channel (`wifi_second_chan_t`)
+    pub second: u32,
+    /// Signal strength in dBm
+    pub rssi: i8,
+    /// Auth mode (`wifi_auth_mode_t`)
+    pub authmode: u32,
+    /// Pairwise cipher (`wifi_cipher_type_t`)
+    pub pairwise_cipher: u32,
+    /// Group cipher (`wifi_cipher_type_t`)
+    pub group_cipher: u32,
+    /// Antenna used to receive the beacon (`wifi_ant_t`)
+    pub ant: u32,
+    pub phy_11b: bool,
+    pub phy_11g: bool,
+    pub phy_11n: bool,
+    pub phy_lr: bool,
+    pub wps: bool,
+    pub ftm_responder: bool,
+    pub ftm_initiator: bool,
+    /// Country code, NUL terminated
+    pub country_code: [u8; 3],
+    /// First channel of the country information
+    pub country_start_channel: u8,
+    /// Number of channels of the country information
+    pub country_num_channels: u8,
+    /// Maximum transmit power of the country information
+    pub country_max_tx_power: i8,
+}
+
+impl ApRecord {
+    pub(crate) fn from_raw(record: &wifi_ap_record_t) -> ApRecord {
+        ApRecord {
+            bssid: record.bssid,
+            ssid: record.ssid,
+            primary: record.primary,
+            second: record.second,
+            rssi: record.rssi,
+            authmode: record.authmode,
+            pairwise_cipher: record.pairwise_cipher,
+            group_cipher: record.group_cipher,
+            ant: record.ant,
+            phy_11b: record.phy_11b() != 0,
+            phy_11g: record.phy_11g() != 0,
+            phy_11n: record.phy_11n() != 0,
+            phy_lr: record.phy_lr() != 0,
+            wps: record.wps() != 0,
+            ftm_responder: record.ftm_responder() != 0,
+            ftm_initiator: record.ftm_initiator() != 0,
+            country_code: record.country.cc.map(|c| c as u8),
+            country_start_channel: record.country.schan,
+            country_num_channels: record.country.nchan,
+            country_max_tx_power: record.country.max_tx_power,
+        }
+    }
+
+    /// Signal strength in dBm
+    pub fn rssi_dbm(&self) -> i8 {
+        self.rssi
+    }
+
+    /// The AP doesn't broadcast its SSID.
+    ///
+    /// These APs are only reported if the scan was started with `ScanConfig::show_hidden`.
+    pub fn is_hidden(&self) -> bool {
+        self.ssid[0] == 0 && self.bssid != [0u8; 6]
+    }
+}
+
+/// How the country information is chosen
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CountryPolicy {
+    /// Use the country information of the AP the station is connected to
+    Auto,
+    /// Always use the configured country information
+    Manual,
+}
+
+/// Regulatory domain used by the driver
+///
+/// The soft-AP advertises this as the country IE of its beacons and probe responses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Country {
+    /// Two letter country code, "01" for world safe mode
+    pub code: [u8; 2],
+    /// First allowed channel
+    pub start_channel: u8,
+    /// Number of allowed channels
+    pub num_channels: u8,
+    /// Maximum transmit power
+    pub max_tx_power: i8,
+    pub policy: CountryPolicy,
+}
+
+impl Country {
+    #[allow(non_upper_case_globals)]
+    pub(crate) fn from_raw(country: &wifi_country_t) -> Country {
+        Country {
+            code: [country.cc[0] as u8, country.cc[1] as u8],
+            start_channel: country.schan,
+            num_channels: country.nchan,
+            max_tx_power: country.max_tx_power,
+            policy: match country.policy {
+                wifi_country_policy_t_WIFI_COUNTRY_POLICY_MANUAL => CountryPolicy::Manual,
+                _ => CountryPolicy::Auto,
+            },
+        }
+    }
+
+    /// Whether a soft-AP includes a country IE in its beacons.
+    /// There is no country IE in world safe mode.
+    pub fn is_advertised(&self) -> bool {
+        &self.code != b"01"
+    }
+}
+
+/// Set the country information used by the driver.
+pub fn set_country(
+    code: [u8; 2],
+    start_channel: u8,
+    num_channels: u8,
+    policy: CountryPolicy,
+) -> i32 {
+    let country = wifi_country_t {
+        cc: [code[0], code[1], 0],
+        schan: start_channel,
+        nchan: num_channels,
+        max_tx_power: 20,
+        policy: match policy {
+            CountryPolicy::Auto => {
+                crate::binary::include::wifi_country_policy_t_WIFI_COUNTRY_POLICY_AUTO
+            }
+            CountryPolicy::Manual => wifi_country_policy_t_WIFI_COUNTRY_POLICY_MANUAL,
+        },
+    };
+
+    unsafe {
+        let res = esp_wifi_set_country(&country);
+        if res == 0 {
+            COUNTRY = Some(country);
+        }
+        res
+    }
+}
+
+/// Read back the country information currently used by the driver
+pub fn get_country() -> Result<Country, i32> {
+    let mut country = wifi_country_t {
+        cc: [0; 3],
+        schan: 0,
+        nchan: 0,
+        max_tx_power: 0,
+        policy: 0,
+    };
+    let res = unsafe { crate::binary::include::esp_wifi_get_country(&mut country) };
+    if res != 0 {
+        return Err(res);
+    }
+
+    Ok(Country::from_raw(&country))
+}
+
+/// Read back the power save mode currently used by the driver
+pub fn get_power_save_mode() -> Result<PowerSaveMode, i32> {
+    let mut ps: wifi_ps_type_t = wifi_ps_type_t_WIFI_PS_NONE;
+    let res = unsafe { esp_wifi_get_ps(&mut ps) };
+    if res != 0 {
+        return Err(res);
+    }
+
+    Ok(PowerSaveMode::from_raw(ps))
+}
+
+/// Set the power save mode of the driver.
+pub fn set_power_save_mode(mode: PowerSaveMode) -> i32 {
+    unsafe {
+        let res = esp_wifi_set_ps(mode.to_raw());
+        if res == 0 {
+            POWER_SAVE_MODE = Some(mode);
+        }
+        res
+    }
+}
+
 #[cfg(all(feature = "esp32c3", coex))]
 static mut G_COEX_ADAPTER_FUNCS: coex_adapter_funcs_t = coex_adapter_funcs_t {
     _version: crate::binary::include::COEX_ADAPTER_VERSION as i32,
@@ -383,6 +757,434 @@ pub fn get_sta_mac(mac: &mut [u8; 6]) {
     }
 }
 
+pub fn get_ap_mac(mac: &mut [u8; 6]) {
+    unsafe {
+        read_mac(mac as *mut u8, 1);
+    }
+}
+
+/// Kind of a frame captured by the sniffer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameKind {
+    Management,
+    Control,
+    Data,
+    /// Other frames like MIMO, these don't carry any data
+    Misc,
+}
+
+/// Selects the frames the sniffer captures
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SnifferFilter {
+    pub management: bool,
+    pub control: bool,
+    pub data: bool,
+    pub misc: bool,
+    /// Only pass beacons of the captured management frames
+    pub beacons_only: bool,
+}
+
+impl SnifferFilter {
+    /// Capture all frames
+    pub fn all() -> SnifferFilter {
+        SnifferFilter {
+            management: true,
+            control: true,
+            data: true,
+            misc: true,
+            beacons_only: false,
+        }
+    }
+
+    /// Capture beacons only
+    pub fn beacons() -> SnifferFilter {
+        SnifferFilter {
+            management: true,
+            control: false,
+            data: false,
+            misc: false,
+            beacons_only: true,
+        }
+    }
+
+    /// Capture data frames only
+    pub fn data() -> SnifferFilter {
+        SnifferFilter {
+            management: false,
+            control: false,
+            data: true,
+            misc: false,
+            beacons_only: false,
+        }
+    }
+
+    fn to_raw(&self) -> u32 {
+        let mut mask = 0;
+        if self.management {
+            mask |= crate::binary::include::WIFI_PROMIS_FILTER_MASK_MGMT;
+        }
+        if self.control {
+            mask |= crate::binary::include::WIFI_PROMIS_FILTER_MASK_CTRL;
+        }
+        if self.data {
+            mask |= crate::binary::include::WIFI_PROMIS_FILTER_MASK_DATA;
+        }
+        if self.misc {
+            mask |= crate::binary::include::WIFI_PROMIS_FILTER_MASK_MISC;
+        }
+        mask
+    }
+}
+
+/// A change of the Wi-Fi state passed to the callback registered via `set_event_callback`
+/// and published to the subscribers of `Wifi`'s `EventBus` implementation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WifiEvent {
+    StaStart,
+    StaStop,
+    StaConnected,
+    /// The station got disconnected or failed to connect
+    StaDisconnected {
+        reason: DisconnectReason,
+    },
+    /// The station got an IPv4 address via DHCP
+    GotIp,
+    /// The signal of the AP dropped below the threshold set via `Wifi::set_rssi_threshold`
+    StaBssRssiLow {
+        rssi: i8,
+    },
+    ScanDone,
+    ApStart,
+    ApStop,
+}
+
+/// Why the station got disconnected, the common `wifi_err_reason_t` values
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisconnectReason {
+    Unspecified,
+    /// The AP deauthenticated the station because it left
+    AuthLeave,
+    /// The AP dropped the station, e.g. after it was inactive for too long
+    AssocExpire,
+    /// The AP can't handle any more stations
+    AssocTooMany,
+    /// The station left
+    AssocLeave,
+    /// Usually a wrong password
+    FourWayHandshakeTimeout,
+    /// The AP stopped sending beacons, it's likely out of range
+    BeaconTimeout,
+    /// No AP with the configured SSID was found
+    NoApFound,
+    /// Authentication failed, usually a wrong password or an unsupported auth method
+    AuthFail,
+    AssocFail,
+    /// The WPA handshake didn't complete, usually a wrong password
+    HandshakeTimeout,
+    ConnectionFail,
+    /// Any other `wifi_err_reason_t`
+    Other(u8),
+}
+
+impl DisconnectReason {
+    #[allow(non_upper_case_globals)]
+    pub(crate) fn from_raw(reason: u8) -> DisconnectReason {
+        use crate::binary::include::*;
+
+        match reason as wifi_err_reason_t {
+            wifi_err_reason_t_WIFI_REASON_UNSPECIFIED => DisconnectReason::Unspecified,
+            wifi_err_reason_t_WIFI_REASON_AUTH_LEAVE => DisconnectReason::AuthLeave,
+            wifi_err_reason_t_WIFI_REASON_ASSOC_EXPIRE => DisconnectReason::AssocExpire,
+            wifi_err_reason_t_WIFI_REASON_ASSOC_TOOMANY => DisconnectReason::AssocTooMany,
+            wifi_err_reason_t_WIFI_REASON_ASSOC_LEAVE => DisconnectReason::AssocLeave,
+            wifi_err_reason_t_WIFI_REASON_4WAY_HANDSHAKE_TIMEOUT => {
+                DisconnectReason::FourWayHandshakeTimeout
+            }
+            wifi_err_reason_t_WIFI_REASON_BEACON_TIMEOUT => DisconnectReason::BeaconTimeout,
+            wifi_err_reason_t_WIFI_REASON_NO_AP_FOUND => DisconnectReason::NoApFound,
+            wifi_err_reason_t_WIFI_REASON_AUTH_FAIL => DisconnectReason::AuthFail,
+            wifi_err_reason_t_WIFI_REASON_ASSOC_FAIL => DisconnectReason::AssocFail,
+            wifi_err_reason_t_WIFI_REASON_HANDSHAKE_TIMEOUT => DisconnectReason::HandshakeTimeout,
+            wifi_err_reason_t_WIFI_REASON_CONNECTION_FAIL => DisconnectReason::ConnectionFail,
+            _ => DisconnectReason::Other(reason),
+        }
+    }
+}
+
+/// Reason of the last time the station got disconnected or failed to connect
+pub(crate) static LAST_DISCONNECT_REASON: Mutex<RefCell<Option<DisconnectReason>>> =
+    Mutex::new(RefCell::new(None));
+
+static EVENT_CB: Mutex<RefCell<Option<fn(WifiEvent)>>> = Mutex::new(RefCell::new(None));
+
+/// Register a function which is called on every `WifiEvent`, `None` removes it.
+///
+/// Except for `GotIp` the callback runs in the context of the Wi-Fi task, keep it short.
+pub fn set_event_callback(cb: Option<fn(WifiEvent)>) {
+    critical_section::with(|cs| *EVENT_CB.borrow_ref_mut(cs) = cb);
+}
+
+pub(crate) fn emit_event(event: WifiEvent) {
+    let cb = critical_section::with(|cs| *EVENT_CB.borrow_ref(cs));
+    if let Some(cb) = cb {
+        cb(event);
+    }
+
+    #[cfg(feature = "embedded-svc")]
+    crate::wifi_interface::event_bus::publish(&event);
+}
+
+static mut SNIFFER_CB: Option<fn(&[u8], FrameKind, i8)> = None;
+static mut SNIFFER_BEACONS_ONLY: bool = false;
+
+/// Put the radio into promiscuous mode and pass the captured frames to `cb`.
+pub fn sniffer_start(filter: SnifferFilter, cb: fn(&[u8], FrameKind, i8)) -> i32 {
+    unsafe {
+        SNIFFER_CB = Some(cb);
+        SNIFFER_BEACONS_ONLY = filter.beacons_only;
+
+        let raw_filter = crate::binary::include::wifi_promiscuous_filter_t {
+            filter_mask: filter.to_raw(),
+        };
+        let res = crate::binary::include::esp_wifi_set_promiscuous_filter(&raw_filter);
+        if res != 0 {
+            return res;
+        }
+
+        if filter.control {
+            let ctrl_filter = crate::binary::include::wifi_promiscuous_filter_t {
+                filter_mask: crate::binary::include::WIFI_PROMIS_CTRL_FILTER_MASK_ALL,
+            };
+            let res = crate::binary::include::esp_wifi_set_promiscuous_ctrl_filter(&ctrl_filter);
+            if res != 0 {
+                return res;
+            }
+        }
+
+        let res = crate::binary::include::esp_wifi_set_promiscuous_rx_cb(Some(promiscuous_rx_cb));
+        if res != 0 {
+            return res;
+        }
+
+        crate::binary::include::esp_wifi_set_promiscuous(true)
+    }
+}
+
+/// Leave promiscuous mode.
+pub fn sniffer_stop() -> i32 {
+    unsafe {
+        let res = crate::binary::include::esp_wifi_set_promiscuous(false);
+        SNIFFER_CB = None;
+        res
+    }
+}
+
+#[allow(non_upper_case_globals)]
+unsafe extern "C" fn promiscuous_rx_cb(
+    buf: *mut crate::binary::c_types::c_void,
+    type_: crate::binary::include::wifi_promiscuous_pkt_type_t,
+) {
+    let cb = match SNIFFER_CB {
+        Some(cb) => cb,
+        None => return,
+    };
+
+    let kind = match type_ {
+        crate::binary::include::wifi_promiscuous_pkt_type_t_WIFI_PKT_MGMT => FrameKind::Management,
+        crate::binary::include::wifi_promiscuous_pkt_type_t_WIFI_PKT_CTRL => FrameKind::Control,
+        crate::binary::include::wifi_promiscuous_pkt_type_t_WIFI_PKT_DATA => FrameKind::Data,
+        _ => FrameKind::Misc,
+    };
+
+    let packet = &*(buf as *const crate::binary::include::wifi_promiscuous_pkt_t);
+    let len = match kind {
+        FrameKind::Misc => 0,
+        _ => packet.rx_ctrl.sig_len() as usize,
+    };
+    let frame = core::slice::from_raw_parts(packet.payload.as_ptr(), len);
+
+    // frame control: type management (0b00), subtype beacon (0b1000)
+    if SNIFFER_BEACONS_ONLY && (kind != FrameKind::Management || frame.first() != Some(&0x80)) {
+        return;
+    }
+
+    cb(frame, kind, packet.rx_ctrl.rssi() as i8);
+}
+
+/// Selects which channel state information (CSI) the driver reports
+///
+/// The defaults are the ones of ESP-IDF.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CsiConfig {
+    /// Report the legacy long training field (LLTF)
+    pub lltf: bool,
+    /// Report the HT long training field (HT-LTF)
+    pub htltf: bool,
+    /// Report the space time block code HT-LTF (STBC-HT-LTF2)
+    pub stbc_htltf2: bool,
+    /// Average the LLTF and HT-LTF of HT packets instead of reporting the HT-LTF directly
+    pub ltf_merge: bool,
+    /// Smooth adjacent sub-carriers
+    pub channel_filter: bool,
+    /// Scale the data by shifting it left by this many bits (0 to 15), `None` scales automatically
+    pub shift: Option<u8>,
+}
+
+impl Default for CsiConfig {
+    fn default() -> CsiConfig {
+        CsiConfig {
+            lltf: true,
+            htltf: true,
+            stbc_htltf2: true,
+            ltf_merge: true,
+            channel_filter: true,
+            shift: None,
+        }
+    }
+}
+
+impl CsiConfig {
+    pub(crate) fn to_raw(&self) -> crate::binary::include::wifi_csi_config_t {
+        crate::binary::include::wifi_csi_config_t {
+            lltf_en: self.lltf,
+            htltf_en: self.htltf,
+            stbc_htltf2_en: self.stbc_htltf2,
+            ltf_merge_en: self.ltf_merge,
+            channel_filter_en: self.channel_filter,
+            manu_scale: self.shift.is_some(),
+            shift: self.shift.unwrap_or(0),
+        }
+    }
+}
+
+/// Channel state information of a single received packet
+#[derive(Debug)]
+pub struct CsiData<'a> {
+    /// The sender of the packet
+    pub mac: [u8; 6],
+    /// RSSI of the packet in dBm
+    pub rssi: i8,
+    /// The primary channel the packet was received on
+    pub channel: u8,
+    /// The secondary channel (0: none, 1: above, 2: below)
+    pub secondary_channel: u8,
+    /// Noise floor in dBm
+    pub noise_floor: i8,
+    /// The first four bytes of `data` are invalid, a hardware limitation
+    pub first_word_invalid: bool,
+    /// The raw CSI, pairs of imaginary and real parts per sub-carrier
+    pub data: &'a [i8],
+}
+
+static mut CSI_CB: Option<fn(&CsiData)> = None;
+
+/// Configure CSI and start passing it to `cb` for every received packet.
+pub fn csi_enable(config: CsiConfig, cb: fn(&CsiData)) -> i32 {
+    unsafe {
+        CSI_CB = Some(cb);
+
+        let raw_config = config.to_raw();
+        let res = crate::binary::include::esp_wifi_set_csi_config(&raw_config);
+        if res != 0 {
+            return res;
+        }
+
+        let res =
+            crate::binary::include::esp_wifi_set_csi_rx_cb(Some(csi_rx_cb), core::ptr::null_mut());
+        if res != 0 {
+            return res;
+        }
+
+        crate::binary::include::esp_wifi_set_csi(true)
+    }
+}
+
+/// Stop reporting CSI and detach the callback.
+pub fn csi_disable() -> i32 {
+    unsafe {
+        let res = crate::binary::include::esp_wifi_set_csi(false);
+        crate::binary::include::esp_wifi_set_csi_rx_cb(None, core::ptr::null_mut());
+        CSI_CB = None;
+        res
+    }
+}
+
+unsafe extern "C" fn csi_rx_cb(
+    _ctx: *mut crate::binary::c_types::c_void,
+    info: *mut crate::binary::include::wifi_csi_info_t,
+) {
+    let cb = match CSI_CB {
+        Some(cb) => cb,
+        None => return,
+    };
+
+    if info.is_null() {
+        return;
+    }
+
+    let info = &*info;
+    let data = if info.buf.is_null() {
+        &[]
+    } else {
+        core::slice::from_raw_parts(info.buf as *const i8, info.len as usize)
+    };
+
+    cb(&CsiData {
+        mac: info.mac,
+        rssi: info.rx_ctrl.rssi() as i8,
+        channel: info.rx_ctrl.channel() as u8,
+        secondary_channel: info.rx_ctrl.secondary_channel() as u8,
+        noise_floor: info.rx_ctrl.noise_floor() as i8,
+        first_word_invalid: info.first_word_invalid,
+        data,
+    });
+}
+
+/// A network interface of the driver
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WifiInterface {
+    /// The station interface
+    Sta,
+    /// The soft-AP interface
+    Ap,
+}
+
+impl WifiInterface {
+    pub(crate) fn to_raw(self) -> wifi_interface_t {
+        match self {
+            WifiInterface::Sta => wifi_interface_t_WIFI_IF_STA,
+            WifiInterface::Ap => wifi_interface_t_WIFI_IF_AP,
+        }
+    }
+}
+
+/// Get the MAC address the driver uses for the given interface.
+pub fn wifi_get_mac(interface: WifiInterface, mac: &mut [u8; 6]) -> i32 {
+    unsafe { crate::binary::include::esp_wifi_get_mac(interface.to_raw(), mac as *mut u8) }
+}
+
+/// Override the MAC address of the given interface.
+pub fn wifi_set_mac(interface: WifiInterface, mac: &[u8; 6]) -> i32 {
+    unsafe { crate::binary::include::esp_wifi_set_mac(interface.to_raw(), mac as *const u8) }
+}
+
+/// Set the 802.11 protocols (a bitmap of `WIFI_PROTOCOL_*`) the given interface uses.
+pub fn wifi_set_protocol(interface: WifiInterface, protocols: u8) -> i32 {
+    unsafe { crate::binary::include::esp_wifi_set_protocol(interface.to_raw(), protocols) }
+}
+
+/// Set the channel bandwidth of the given interface.
+pub fn wifi_set_bandwidth(interface: WifiInterface, bandwidth: Bandwidth) -> i32 {
+    unsafe {
+        crate::binary::include::esp_wifi_set_bandwidth(interface.to_raw(), bandwidth.to_raw())
+    }
+}
+
+#[cfg(coex)]
+pub fn coex_set_mode(mode: CoexMode) -> i32 {
+    unsafe { crate::binary::include::coex_preference_set(mode.to_raw()) }
+}
+
 pub fn wifi_init() -> i32 {
     unsafe {
         G_CONFIG.wpa_crypto_funcs = g_wifi_default_wpa_crypto_funcs;
@@ -469,18 +1271,54 @@ unsafe extern "C" fn recv_cb(
     len: u16,
     eb: *mut crate::binary::c_types::c_void,
 ) -> esp_err_t {
-    critical_section::with(|cs| {
+    let res = critical_section::with(|cs| {
         let mut queue = DATA_QUEUE_RX.borrow_ref_mut(cs);
-        if !queue.is_full() {
-            let src = core::slice::from_raw_parts_mut(buffer as *mut u8, len as usize);
-            let packet = DataFrame::from_bytes(src);
-            queue.enqueue(packet);
+        let src = core::slice::from_raw_parts_mut(buffer as *mut u8, len as usize);
+        if queue.enqueue(src) {
             esp_wifi_internal_free_rx_buffer(eb);
             0
         } else {
+            DEVICE_STATS.borrow_ref_mut(cs).rx_dropped += 1;
             1
         }
-    })
+    });
+
+    #[cfg(feature = "async")]
+    if res == 0 {
+        wake_rx_wakers();
+    }
+
+    res
+}
+
+/// Wakers of the futures waiting for a frame to be received
+#[cfg(feature = "async")]
+static RX_WAKERS: Mutex<RefCell<heapless::Vec<core::task::Waker, 4>>> =
+    Mutex::new(RefCell::new(heapless::Vec::new()));
+
+/// Wake `waker` once the next frame was received.
+///
+/// If there are too many wakers registered already all of them are woken right away.
+#[cfg(feature = "async")]
+pub(crate) fn register_rx_waker(waker: &core::task::Waker) {
+    let registered = critical_section::with(|cs| {
+        let mut wakers = RX_WAKERS.borrow_ref_mut(cs);
+        wakers.iter().any(|registered| registered.will_wake(waker))
+            || wakers.push(waker.clone()).is_ok()
+    });
+
+    if !registered {
+        wake_rx_wakers();
+        waker.wake_by_ref();
+    }
+}
+
+#[cfg(feature = "async")]
+fn wake_rx_wakers() {
+    let wakers = critical_section::with(|cs| core::mem::take(&mut *RX_WAKERS.borrow_ref_mut(cs)));
+    for waker in wakers {
+        waker.wake();
+    }
 }
 
 #[ram]
@@ -500,24 +1338,28 @@ pub fn wifi_start() -> i32 {
             return res;
         }
 
+        // the settings applied before a `wifi_stop` are restored, the defaults otherwise
+
         // To make this fully work we probably need to implement some level of PM support!
         #[cfg(coex)]
-        let res = esp_wifi_set_ps(crate::binary::include::wifi_ps_type_t_WIFI_PS_MAX_MODEM);
+        let default_ps = crate::binary::include::wifi_ps_type_t_WIFI_PS_MAX_MODEM;
 
         #[cfg(not(coex))]
-        let res = esp_wifi_set_ps(crate::binary::include::wifi_ps_type_t_WIFI_PS_NONE);
+        let default_ps = crate::binary::include::wifi_ps_type_t_WIFI_PS_NONE;
+
+        let res = esp_wifi_set_ps(POWER_SAVE_MODE.map_or(default_ps, |mode| mode.to_raw()));
         if res != 0 {
             return res;
         }
 
         let cntry_code = [b'C', b'N', 0];
-        let country = wifi_country_t {
+        let country = COUNTRY.unwrap_or(wifi_country_t {
             cc: cntry_code,
             schan: 1,
             nchan: 13,
             max_tx_power: 20,
             policy: wifi_country_policy_t_WIFI_COUNTRY_POLICY_MANUAL,
-        };
+        });
         let res = esp_wifi_set_country(&country);
         if res != 0 {
             return res;
@@ -537,39 +1379,103 @@ unsafe extern "C" fn coex_register_start_cb(
     0
 }
 
-pub fn wifi_start_scan() -> i32 {
+/// Restricts what a scan looks for, the default scans all channels for all APs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ScanConfig<'a> {
+    /// Only report APs with this SSID
+    pub ssid: Option<&'a str>,
+    /// Only scan this channel instead of all of them
+    pub channel: Option<u8>,
+    /// Listen for beacons instead of sending probe requests
+    pub passive: bool,
+    /// Also report APs which don't broadcast their SSID, they have an empty SSID
+    pub show_hidden: bool,
+    /// Minimum time per channel of an active scan, 10 ms if not set
+    pub active_min_ms: Option<u32>,
+    /// Maximum time per channel of an active scan, 20 ms if not set
+    pub active_max_ms: Option<u32>,
+    /// Time per channel of a passive scan, 20 ms if not set
+    pub passive_ms: Option<u32>,
+}
+
+impl<'a> ScanConfig<'a> {
+    /// The minimum and maximum time per channel of an active scan.
+    pub(crate) fn active_time_ms(&self) -> (u32, u32) {
+        (
+            self.active_min_ms.unwrap_or(10),
+            self.active_max_ms.unwrap_or(20),
+        )
+    }
+}
+
+pub fn wifi_start_scan(config: &ScanConfig) -> i32 {
+    start_scan(config, true)
+}
+
+/// Start a scan without waiting for it to finish, see `take_scan_done`.
+pub fn wifi_begin_scan(config: &ScanConfig) -> i32 {
+    start_scan(config, false)
+}
+
+fn start_scan(config: &ScanConfig, block: bool) -> i32 {
+    let (active_min, active_max) = config.active_time_ms();
     let scan_time = wifi_scan_time_t {
-        active: wifi_active_scan_time_t { min: 10, max: 20 },
-        passive: 20,
+        active: wifi_active_scan_time_t {
+            min: active_min,
+            max: active_max,
+        },
+        passive: config.passive_ms.unwrap_or(20),
     };
 
+    // the driver expects a null terminated SSID
+    let mut ssid = [0u8; 33];
+    if let Some(config_ssid) = config.ssid {
+        let len = config_ssid.len().min(32);
+        ssid[..len].copy_from_slice(&config_ssid.as_bytes()[..len]);
+    }
+
     let scan_config = wifi_scan_config_t {
-        ssid: core::ptr::null_mut(),
+        ssid: if config.ssid.is_some() {
+            ssid.as_mut_ptr()
+        } else {
+            core::ptr::null_mut()
+        },
         bssid: core::ptr::null_mut(),
-        channel: 0,
-        show_hidden: false,
-        scan_type: wifi_scan_type_t_WIFI_SCAN_TYPE_ACTIVE,
+        channel: config.channel.unwrap_or(0),
+        show_hidden: config.show_hidden,
+        scan_type: if config.passive {
+            crate::binary::include::wifi_scan_type_t_WIFI_SCAN_TYPE_PASSIVE
+        } else {
+            wifi_scan_type_t_WIFI_SCAN_TYPE_ACTIVE
+        },
         scan_time: scan_time,
     };
 
-    unsafe { esp_wifi_scan_start(&scan_config, true) }
+    unsafe {
+        SCAN_DONE = false;
+        esp_wifi_scan_start(&scan_config, block)
+    }
 }
 
-pub fn wifi_connect(ssid: &str, password: &str) -> i32 {
+/// Apply the station configuration to the driver without connecting.
+/// If `channel` is not 0 only that channel is scanned for the AP before associating,
+/// which speeds up connecting to an AP with a known channel but unknown BSSID.
+/// With a `bssid` only the AP with that BSSID is connected to.
+pub fn wifi_set_sta_config(ssid: &str, password: &str, channel: u8, bssid: Option<[u8; 6]>) -> i32 {
     unsafe {
         let mut cfg = wifi_config_t {
             sta: wifi_sta_config_t {
                 ssid: [0; 32],
                 password: [0; 64],
                 scan_method: wifi_scan_method_t_WIFI_FAST_SCAN,
-                bssid_set: false,
-                bssid: [0; 6],
-                channel: 0,
-                listen_interval: 3,
+                bssid_set: bssid.is_some(),
+                bssid: bssid.unwrap_or([0; 6]),
+                channel,
+                listen_interval: STA_LISTEN_INTERVAL,
                 sort_method: wifi_sort_method_t_WIFI_CONNECT_AP_BY_SIGNAL,
                 threshold: wifi_scan_threshold_t {
                     rssi: -99,
-                    authmode: wifi_auth_mode_t_WIFI_AUTH_OPEN,
+                    authmode: STA_MIN_AUTH,
                 },
                 pmf_cfg: wifi_pmf_config_t {
                     capable: true,
@@ -584,25 +1490,399 @@ pub fn wifi_connect(ssid: &str, password: &str) -> i32 {
         cfg.sta.ssid[0..(ssid.len())].copy_from_slice(ssid.as_bytes());
         cfg.sta.password[0..(password.len())].copy_from_slice(password.as_bytes());
 
-        let res = esp_wifi_set_config(wifi_interface_t_WIFI_IF_STA, &mut cfg);
+        esp_wifi_set_config(wifi_interface_t_WIFI_IF_STA, &mut cfg)
+    }
+}
+
+/// Switch the driver to the given mode.
+///
+/// Frames of the network stack are sent and received on the station interface unless the
+/// soft-AP is the only active interface.
+pub fn wifi_set_mode(mode: wifi_mode_t) -> i32 {
+    unsafe {
+        let res = esp_wifi_set_mode(mode);
         if res != 0 {
             return res;
         }
 
-        esp_wifi_connect()
+        if mode == wifi_mode_t_WIFI_MODE_AP {
+            let res = esp_wifi_internal_reg_rxcb(esp_interface_t_ESP_IF_WIFI_AP, Some(recv_cb));
+            if res != 0 {
+                return res;
+            }
+            DATA_INTERFACE = wifi_interface_t_WIFI_IF_AP;
+        } else {
+            DATA_INTERFACE = wifi_interface_t_WIFI_IF_STA;
+        }
     }
+
+    0
+}
+
+/// Check if the network stack is bound to the soft-AP interface.
+pub fn is_ap_data_interface() -> bool {
+    unsafe { DATA_INTERFACE == wifi_interface_t_WIFI_IF_AP }
+}
+
+/// Apply the soft-AP configuration to the driver.
+/// With an empty `password` the network is open regardless of `authmode`.
+pub fn wifi_set_ap_config(
+    ssid: &str,
+    password: &str,
+    channel: u8,
+    max_connections: u8,
+    authmode: wifi_auth_mode_t,
+    ssid_hidden: bool,
+) -> i32 {
+    unsafe {
+        let mut cfg = wifi_config_t {
+            ap: wifi_ap_config_t {
+                ssid: [0; 32],
+                password: [0; 64],
+                ssid_len: ssid.len() as u8,
+                channel,
+                authmode: if password.is_empty() {
+                    wifi_auth_mode_t_WIFI_AUTH_OPEN
+                } else {
+                    authmode
+                },
+                ssid_hidden: ssid_hidden as u8,
+                max_connection: max_connections,
+                beacon_interval: 100,
+                pairwise_cipher: crate::binary::include::wifi_cipher_type_t_WIFI_CIPHER_TYPE_CCMP,
+                ftm_responder: false,
+                pmf_cfg: wifi_pmf_config_t {
+                    capable: true,
+                    required: false,
+                },
+            },
+        };
+
+        cfg.ap.ssid[0..(ssid.len())].copy_from_slice(ssid.as_bytes());
+        cfg.ap.password[0..(password.len())].copy_from_slice(password.as_bytes());
+
+        esp_wifi_set_config(wifi_interface_t_WIFI_IF_AP, &mut cfg)
+    }
+}
+
+/// Switch to soft-AP mode and start the access point with the given configuration.
+pub fn wifi_start_ap(
+    ssid: &str,
+    password: &str,
+    channel: u8,
+    max_connections: u8,
+    authmode: wifi_auth_mode_t,
+    ssid_hidden: bool,
+) -> i32 {
+    let res = wifi_set_mode(wifi_mode_t_WIFI_MODE_AP);
+    if res != 0 {
+        return res;
+    }
+
+    wifi_set_ap_config(
+        ssid,
+        password,
+        channel,
+        max_connections,
+        authmode,
+        ssid_hidden,
+    )
+}
+
+/// Credentials for a WPA2-Enterprise (802.1X) network using PEAP or TTLS
+#[derive(Debug, Clone, Copy)]
+pub struct EnterpriseConfig<'a> {
+    pub ssid: &'a str,
+    /// The outer identity sent unencrypted, usually `anonymous@<realm>`
+    pub identity: &'a str,
+    pub username: &'a str,
+    pub password: &'a str,
+    /// The CA certificate to verify the authentication server with, PEM (including the
+    /// terminating NUL) or DER encoded. Without it the server isn't verified.
+    ///
+    /// The driver keeps a pointer to it, that's why it has to be `'static`.
+    pub ca_cert: Option<&'static [u8]>,
+}
+
+extern "C" {
+    fn esp_wifi_sta_wpa2_ent_set_identity(
+        identity: *const u8,
+        len: crate::binary::c_types::c_int,
+    ) -> esp_err_t;
+    fn esp_wifi_sta_wpa2_ent_set_username(
+        username: *const u8,
+        len: crate::binary::c_types::c_int,
+    ) -> esp_err_t;
+    fn esp_wifi_sta_wpa2_ent_set_password(
+        password: *const u8,
+        len: crate::binary::c_types::c_int,
+    ) -> esp_err_t;
+    fn esp_wifi_sta_wpa2_ent_set_ca_cert(
+        ca_cert: *const u8,
+        len: crate::binary::c_types::c_int,
+    ) -> esp_err_t;
+    fn esp_wifi_sta_wpa2_ent_clear_ca_cert();
+    fn esp_wifi_sta_wpa2_ent_enable() -> esp_err_t;
+    fn esp_wifi_sta_wpa2_ent_disable() -> esp_err_t;
+}
+
+/// Pass the credentials of `config` to the supplicant and enable WPA2-Enterprise.
+///
+/// The SSID has to be set via `wifi_set_sta_config` separately.
+pub fn wifi_enterprise_enable(config: &EnterpriseConfig) -> i32 {
+    unsafe {
+        let res = esp_wifi_sta_wpa2_ent_set_identity(
+            config.identity.as_ptr(),
+            config.identity.len() as crate::binary::c_types::c_int,
+        );
+        if res != 0 {
+            return res;
+        }
+
+        let res = esp_wifi_sta_wpa2_ent_set_username(
+            config.username.as_ptr(),
+            config.username.len() as crate::binary::c_types::c_int,
+        );
+        if res != 0 {
+            return res;
+        }
+
+        let res = esp_wifi_sta_wpa2_ent_set_password(
+            config.password.as_ptr(),
+            config.password.len() as crate::binary::c_types::c_int,
+        );
+        if res != 0 {
+            return res;
+        }
+
+        match config.ca_cert {
+            Some(ca_cert) => {
+                let res = esp_wifi_sta_wpa2_ent_set_ca_cert(
+                    ca_cert.as_ptr(),
+                    ca_cert.len() as crate::binary::c_types::c_int,
+                );
+                if res != 0 {
+                    return res;
+                }
+            }
+            None => esp_wifi_sta_wpa2_ent_clear_ca_cert(),
+        }
+
+        esp_wifi_sta_wpa2_ent_enable()
+    }
+}
+
+/// Go back to connecting with a pre-shared key.
+pub fn wifi_enterprise_disable() -> i32 {
+    unsafe { esp_wifi_sta_wpa2_ent_disable() }
+}
+
+/// Connect to the AP using the station configuration previously applied to the driver.
+pub fn wifi_connect() -> i32 {
+    unsafe { esp_wifi_connect() }
+}
+
+/// Only connect to APs using at least the given authentication method.
+///
+/// Applies to the current station configuration and all the ones set later.
+pub fn wifi_set_sta_min_auth(authmode: wifi_auth_mode_t) -> i32 {
+    unsafe {
+        STA_MIN_AUTH = authmode;
+    }
+
+    update_sta_config(|sta| sta.threshold.authmode = authmode)
+}
+
+/// Set the listen interval used in max modem power save mode, in beacon intervals.
+///
+/// Applies to the current station configuration and all the ones set later.
+pub fn wifi_set_sta_listen_interval(interval: u16) -> i32 {
+    unsafe {
+        STA_LISTEN_INTERVAL = interval;
+    }
+
+    update_sta_config(|sta| sta.listen_interval = interval)
+}
+
+/// Modify the station configuration kept by the driver.
+fn update_sta_config(f: impl FnOnce(&mut wifi_sta_config_t)) -> i32 {
+    unsafe {
+        let mut cfg: wifi_config_t = core::mem::zeroed();
+        let res =
+            crate::binary::include::esp_wifi_get_config(wifi_interface_t_WIFI_IF_STA, &mut cfg);
+        if res != 0 {
+            return res;
+        }
+
+        f(&mut cfg.sta);
+
+        esp_wifi_set_config(wifi_interface_t_WIFI_IF_STA, &mut cfg)
+    }
+}
+
+/// Clear the SSID, password and BSSID of the station configuration kept by the driver.
+/// With flash storage this also removes the credentials persisted in NVS.
+pub fn wifi_clear_sta_config() -> i32 {
+    unsafe {
+        let mut cfg: wifi_config_t = core::mem::zeroed();
+        let res =
+            crate::binary::include::esp_wifi_get_config(wifi_interface_t_WIFI_IF_STA, &mut cfg);
+        if res != 0 {
+            return res;
+        }
+
+        cfg.sta.ssid = [0; 32];
+        cfg.sta.password = [0; 64];
+        cfg.sta.bssid_set = false;
+        cfg.sta.bssid = [0; 6];
+
+        esp_wifi_set_config(wifi_interface_t_WIFI_IF_STA, &mut cfg)
+    }
+}
+
+pub fn wifi_disconnect() -> i32 {
+    unsafe { crate::binary::include::esp_wifi_disconnect() }
 }
 
 pub fn wifi_stop() -> i32 {
     unsafe { esp_wifi_stop() }
 }
 
+/// Free all the resources of the driver, `wifi_init` and `wifi_start` bring it back.
+pub fn wifi_deinit() -> i32 {
+    // `wifi_init` initializes the supplicant again
+    let res = unsafe { crate::binary::include::esp_supplicant_deinit() };
+    if res != 0 {
+        return res;
+    }
+
+    let res = unsafe { crate::binary::include::esp_wifi_deinit() };
+
+    critical_section::with(|cs| {
+        DATA_QUEUE_RX.borrow_ref_mut(cs).clear();
+        DATA_QUEUE_TX.borrow_ref_mut(cs).clear();
+    });
+
+    res
+}
+
+/// Number of frames and bytes passed between the driver and smoltcp.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DeviceStats {
+    pub rx_packets: u32,
+    pub rx_bytes: u64,
+    /// Received frames dropped because the receive queue was full
+    pub rx_dropped: u32,
+    pub tx_packets: u32,
+    pub tx_bytes: u64,
+    /// Frames dropped because the transmit queue was full
+    pub tx_dropped: u32,
+    /// Frames rejected by the driver
+    pub tx_errors: u32,
+}
+
+impl DeviceStats {
+    const fn new() -> DeviceStats {
+        DeviceStats {
+            rx_packets: 0,
+            rx_bytes: 0,
+            rx_dropped: 0,
+            tx_packets: 0,
+            tx_bytes: 0,
+            tx_dropped: 0,
+            tx_errors: 0,
+        }
+    }
+}
+
+/// The default IP MTU of the device, the maximum of an ethernet frame.
+pub const DEFAULT_MTU: usize = 1500;
+
+const ETHERNET_HEADER_LEN: usize = 14;
+
 /// A wifi device implementing smoltcp's Device trait.
-pub struct WifiDevice {}
+pub struct WifiDevice {
+    rx_frame_hook: Option<fn(&[u8])>,
+    tx_frame_hook: Option<fn(&[u8])>,
+    mtu: usize,
+}
 
 impl WifiDevice {
     pub fn new() -> WifiDevice {
-        WifiDevice {}
+        static mut RX_FRAMES: [DataFrame; DEFAULT_RX_QUEUE_SIZE] =
+            [DataFrame::new(); DEFAULT_RX_QUEUE_SIZE];
+        static mut TX_FRAMES: [DataFrame; DEFAULT_TX_QUEUE_SIZE] =
+            [DataFrame::new(); DEFAULT_TX_QUEUE_SIZE];
+
+        let mut device = WifiDevice {
+            rx_frame_hook: None,
+            tx_frame_hook: None,
+            mtu: DEFAULT_MTU,
+        };
+        unsafe { device.set_queues(&mut RX_FRAMES, &mut TX_FRAMES) };
+        device
+    }
+
+    /// Use `rx_frames` and `tx_frames` as the storage of the receive and transmit queues.
+    ///
+    /// Each frame takes about 1.5 KiB, more frames mean fewer drops under bursty traffic
+    /// (counted in `DeviceStats`). The queues are shared by all instances, frames queued at the
+    /// time are dropped. Neither queue may be empty.
+    pub fn set_queues(
+        &mut self,
+        rx_frames: &'static mut [DataFrame],
+        tx_frames: &'static mut [DataFrame],
+    ) {
+        assert!(!rx_frames.is_empty() && !tx_frames.is_empty());
+
+        critical_section::with(|cs| {
+            DATA_QUEUE_RX.borrow_ref_mut(cs).set_storage(rx_frames);
+            DATA_QUEUE_TX.borrow_ref_mut(cs).set_storage(tx_frames);
+        });
+    }
+
+    /// Set the largest IP packet smoltcp sends, without the ethernet header.
+    ///
+    /// smoltcp derives the MSS it advertises in TCP SYNs from it, too. Frames have to fit into a
+    /// `DataFrame`, `Wifi::set_mtu` checks the range before calling this.
+    pub(crate) fn set_mtu(&mut self, mtu: usize) {
+        self.mtu = mtu;
+    }
+
+    /// Get the largest IP packet smoltcp sends, without the ethernet header.
+    pub fn mtu(&self) -> usize {
+        self.mtu
+    }
+
+    /// Register a hook which gets every received ethernet frame before it's handed to smoltcp.
+    /// The hook runs in a critical section, keep it short.
+    pub fn on_rx_frame(&mut self, hook: Option<fn(&[u8])>) {
+        self.rx_frame_hook = hook;
+    }
+
+    /// Register a hook which gets every ethernet frame produced by smoltcp before it's sent.
+    /// The hook runs in a critical section, keep it short.
+    pub fn on_tx_frame(&mut self, hook: Option<fn(&[u8])>) {
+        self.tx_frame_hook = hook;
+    }
+
+    /// Get the frame counters.
+    ///
+    /// The counters are shared by all instances and count from the start of the driver.
+    pub fn stats(&self) -> DeviceStats {
+        critical_section::with(|cs| *DEVICE_STATS.borrow_ref(cs))
+    }
+
+    fn rx_token(&self) -> WifiRxToken {
+        WifiRxToken {
+            frame_hook: self.rx_frame_hook,
+        }
+    }
+
+    fn tx_token(&self) -> WifiTxToken {
+        WifiTxToken {
+            frame_hook: self.tx_frame_hook,
+        }
     }
 }
 
@@ -617,7 +1897,7 @@ impl<'a> Device<'a> for WifiDevice {
             let queue = DATA_QUEUE_RX.borrow_ref_mut(cs);
 
             if !queue.is_empty() {
-                Some((WifiRxToken::default(), WifiTxToken::default()))
+                Some((self.rx_token(), self.tx_token()))
             } else {
                 None
             }
@@ -625,19 +1905,22 @@ impl<'a> Device<'a> for WifiDevice {
     }
 
     fn transmit(&'a mut self) -> Option<Self::TxToken> {
-        Some(WifiTxToken::default())
+        Some(self.tx_token())
     }
 
     fn capabilities(&self) -> smoltcp::phy::DeviceCapabilities {
         let mut caps = DeviceCapabilities::default();
-        caps.max_transmission_unit = 1514;
+        // for ethernet smoltcp expects the MTU to include the ethernet header
+        caps.max_transmission_unit = self.mtu + ETHERNET_HEADER_LEN;
         caps.max_burst_size = Some(1);
         caps
     }
 }
 
 #[derive(Debug, Default)]
-pub struct WifiRxToken {}
+pub struct WifiRxToken {
+    frame_hook: Option<fn(&[u8])>,
+}
 
 impl RxToken for WifiRxToken {
     fn consume<R, F>(self, _timestamp: smoltcp::time::Instant, f: F) -> smoltcp::Result<R>
@@ -652,6 +1935,15 @@ impl RxToken for WifiRxToken {
                     unsafe { core::slice::from_raw_parts(&data.data as *const u8, data.len) };
                 debug!("received {:?}", _timestamp);
                 dump_packet_info(&buffer);
+                record_dhcp_lease(cs, &buffer);
+                {
+                    let mut stats = DEVICE_STATS.borrow_ref_mut(cs);
+                    stats.rx_packets += 1;
+                    stats.rx_bytes += data.len as u64;
+                }
+                if let Some(hook) = self.frame_hook {
+                    hook(buffer);
+                }
                 f(&mut data.data[..])
             } else {
                 Err(smoltcp::Error::Exhausted)
@@ -661,7 +1953,9 @@ impl RxToken for WifiRxToken {
 }
 
 #[derive(Debug, Default)]
-pub struct WifiTxToken {}
+pub struct WifiTxToken {
+    frame_hook: Option<fn(&[u8])>,
+}
 
 impl TxToken for WifiTxToken {
     fn consume<R, F>(
@@ -673,45 +1967,183 @@ impl TxToken for WifiTxToken {
     where
         F: FnOnce(&mut [u8]) -> smoltcp::Result<R>,
     {
-        let res = unsafe { f(&mut TX_BUFFER[..len]) };
-
-        match res {
-            Ok(_) => critical_section::with(|_| unsafe {
-                if !TX_QUEUED {
-                    TX_QUEUED_DATA_LEN = len as u16;
-                    TX_QUEUED = true;
-                    res
-                } else {
-                    Err(smoltcp::Error::Exhausted)
+        critical_section::with(|cs| {
+            let mut queue = DATA_QUEUE_TX.borrow_ref_mut(cs);
+
+            let frame = match queue.next_free() {
+                Some(frame) => frame,
+                None => {
+                    DEVICE_STATS.borrow_ref_mut(cs).tx_dropped += 1;
+                    return Err(smoltcp::Error::Exhausted);
                 }
-            }),
-            Err(_) => res,
-        }
+            };
+
+            let res = f(&mut frame.data[..len])?;
+            frame.len = add_dhcp_hostname(cs, &mut frame.data, len);
+            if let Some(hook) = self.frame_hook {
+                hook(&frame.data[..frame.len]);
+            }
+            queue.push();
+            Ok(res)
+        })
     }
 }
 
+/// Check if there are received frames waiting to be processed by smoltcp.
+pub fn rx_pending() -> bool {
+    critical_section::with(|cs| !DATA_QUEUE_RX.borrow_ref(cs).is_empty())
+}
+
 pub fn send_data_if_needed() {
-    let to_send = critical_section::with(|_| unsafe {
-        if TX_QUEUED {
-            debug!("sending... {} bytes", TX_QUEUED_DATA_LEN);
-            dump_packet_info(&TX_BUFFER);
-            TX_QUEUED = false;
-            Some((TX_BUFFER, TX_QUEUED_DATA_LEN))
-        } else {
-            None
-        }
-    });
+    while let Some(frame) = critical_section::with(|cs| DATA_QUEUE_TX.borrow_ref_mut(cs).dequeue())
+    {
+        debug!("sending... {} bytes", frame.len);
+        dump_packet_info(&frame.data[..frame.len]);
 
-    if let Some((data, len)) = to_send {
+        let len = frame.len as u16;
         unsafe {
-            let _res = esp_wifi_internal_tx(
-                wifi_interface_t_WIFI_IF_STA,
-                &data as *const _ as *mut crate::binary::c_types::c_void,
+            let res = esp_wifi_internal_tx(
+                DATA_INTERFACE,
+                &frame.data as *const _ as *mut crate::binary::c_types::c_void,
                 len,
             );
-            debug!("esp_wifi_internal_tx {}", _res);
+            debug!("esp_wifi_internal_tx {}", res);
+
+            critical_section::with(|cs| {
+                let mut stats = DEVICE_STATS.borrow_ref_mut(cs);
+                if res == 0 {
+                    stats.tx_packets += 1;
+                    stats.tx_bytes += len as u64;
+                } else {
+                    stats.tx_errors += 1;
+                }
+            });
+        }
+    }
+}
+
+fn record_dhcp_lease(cs: critical_section::CriticalSection, buffer: &[u8]) {
+    use smoltcp::wire::{
+        DhcpMessageType, DhcpPacket, DhcpRepr, EthernetFrame, EthernetProtocol, IpProtocol,
+        Ipv4Packet, UdpPacket, DHCP_CLIENT_PORT,
+    };
+
+    let ef = match EthernetFrame::new_checked(buffer) {
+        Ok(ef) if ef.ethertype() == EthernetProtocol::Ipv4 => ef,
+        _ => return,
+    };
+    let ip = match Ipv4Packet::new_checked(ef.payload()) {
+        Ok(ip) if ip.protocol() == IpProtocol::Udp => ip,
+        _ => return,
+    };
+    let udp = match UdpPacket::new_checked(ip.payload()) {
+        Ok(udp) if udp.dst_port() == DHCP_CLIENT_PORT => udp,
+        _ => return,
+    };
+    let dhcp = match DhcpPacket::new_checked(udp.payload()) {
+        Ok(dhcp) => dhcp,
+        Err(_) => return,
+    };
+
+    if let Ok(repr) = DhcpRepr::parse(&dhcp) {
+        let mut mac = [0u8; 6];
+        get_sta_mac(&mut mac);
+
+        if repr.message_type == DhcpMessageType::Ack
+            && repr.client_hardware_address.as_bytes() == &mac[..]
+        {
+            if let Some(lease_time) = repr.lease_duration {
+                DHCP_LEASE
+                    .borrow_ref_mut(cs)
+                    .replace((lease_time, crate::current_millis()));
+            }
+        }
+    }
+}
+
+/// Add the hostname option (12) to an outgoing DHCP message.
+///
+/// smoltcp's DHCP socket can't send a hostname so the option is inserted into the frame.
+/// Returns the new length of the frame.
+fn add_dhcp_hostname(
+    cs: critical_section::CriticalSection,
+    buffer: &mut [u8],
+    len: usize,
+) -> usize {
+    use smoltcp::wire::{
+        EthernetFrame, EthernetProtocol, IpAddress, IpProtocol, Ipv4Packet, UdpPacket,
+        DHCP_SERVER_PORT,
+    };
+
+    const ETHERNET_HEADER_LEN: usize = 14;
+    const UDP_HEADER_LEN: usize = 8;
+    // fixed part of the message and the magic cookie
+    const DHCP_OPTIONS_OFFSET: usize = 240;
+    const DHCP_OPT_PAD: u8 = 0;
+    const DHCP_OPT_HOSTNAME: u8 = 12;
+    const DHCP_OPT_END: u8 = 255;
+
+    let hostname = DHCP_HOSTNAME.borrow_ref(cs);
+    let hostname = match hostname.as_ref() {
+        Some(hostname) => hostname.as_bytes(),
+        None => return len,
+    };
+
+    let ip_header_len = {
+        let ef = match EthernetFrame::new_checked(&buffer[..len]) {
+            Ok(ef) if ef.ethertype() == EthernetProtocol::Ipv4 => ef,
+            _ => return len,
+        };
+        let ip = match Ipv4Packet::new_checked(ef.payload()) {
+            Ok(ip) if ip.protocol() == IpProtocol::Udp => ip,
+            _ => return len,
+        };
+        match UdpPacket::new_checked(ip.payload()) {
+            Ok(udp) if udp.dst_port() == DHCP_SERVER_PORT => (),
+            _ => return len,
+        };
+        ip.header_len() as usize
+    };
+
+    let udp_start = ETHERNET_HEADER_LEN + ip_header_len;
+    let mut pos = udp_start + UDP_HEADER_LEN + DHCP_OPTIONS_OFFSET;
+    let end = loop {
+        match buffer[..len].get(pos) {
+            Some(&DHCP_OPT_PAD) => pos += 1,
+            Some(&DHCP_OPT_END) => break pos,
+            Some(&DHCP_OPT_HOSTNAME) => return len,
+            Some(_) if pos + 1 < len => pos += 2 + buffer[pos + 1] as usize,
+            _ => return len,
         }
+    };
+
+    let option_len = 2 + hostname.len();
+    if len + option_len > buffer.len() {
+        return len;
     }
+
+    buffer.copy_within(end..len, end + option_len);
+    buffer[end] = DHCP_OPT_HOSTNAME;
+    buffer[end + 1] = hostname.len() as u8;
+    buffer[end + 2..end + option_len].copy_from_slice(hostname);
+    let len = len + option_len;
+
+    let mut ip = Ipv4Packet::new_unchecked(&mut buffer[ETHERNET_HEADER_LEN..len]);
+    ip.set_total_len(ip.total_len() + option_len as u16);
+    ip.fill_checksum();
+    let src_addr = IpAddress::Ipv4(ip.src_addr());
+    let dst_addr = IpAddress::Ipv4(ip.dst_addr());
+
+    let mut udp = UdpPacket::new_unchecked(&mut buffer[udp_start..len]);
+    udp.set_len(udp.len() + option_len as u16);
+    udp.fill_checksum(&src_addr, &dst_addr);
+
+    len
+}
+
+/// Set the hostname sent in DHCP messages, `None` sends none.
+pub(crate) fn set_dhcp_hostname(hostname: Option<heapless::String<32>>) {
+    critical_section::with(|cs| *DHCP_HOSTNAME.borrow_ref_mut(cs) = hostname);
 }
 
 fn dump_packet_info(buffer: &[u8]) {
@@ -17,6 +17,7 @@ use crate::{
         work_queue::queue_work,
     },
     memory_fence::memory_fence,
+    wifi::{DisconnectReason, WifiEvent},
 };
 
 #[cfg(feature = "esp32c3")]
@@ -50,6 +51,35 @@ pub fn get_wifi_state() -> WifiState {
     }
 }
 
+pub static mut SCAN_DONE: bool = false;
+
+/// Check if a scan finished since the last call.
+pub fn take_scan_done() -> bool {
+    critical_section::with(|_| unsafe {
+        let done = SCAN_DONE;
+        SCAN_DONE = false;
+        done
+    })
+}
+
+pub static mut AP_STATE: i32 = -1;
+
+#[derive(Debug, Clone, Copy)]
+pub enum ApState {
+    ApStart,
+    ApStop,
+    Invalid,
+}
+
+#[allow(non_upper_case_globals)]
+pub fn get_ap_state() -> ApState {
+    match unsafe { AP_STATE as u32 } {
+        wifi_event_t_WIFI_EVENT_AP_START => ApState::ApStart,
+        wifi_event_t_WIFI_EVENT_AP_STOP => ApState::ApStop,
+        _ => ApState::Invalid,
+    }
+}
+
 /****************************************************************************
  * Name: wifi_env_is_chip
  *
@@ -909,8 +939,61 @@ pub unsafe extern "C" fn event_post(
         WIFI_STATE = event_id;
     }
 
+    #[allow(non_upper_case_globals)]
+    match event_id as u32 {
+        wifi_event_t_WIFI_EVENT_AP_START | wifi_event_t_WIFI_EVENT_AP_STOP => {
+            AP_STATE = event_id;
+        }
+        wifi_event_t_WIFI_EVENT_SCAN_DONE => {
+            SCAN_DONE = true;
+        }
+        _ => (),
+    }
+
+    if event_id as u32 == wifi_event_t_WIFI_EVENT_STA_CONNECTED {
+        critical_section::with(|cs| {
+            crate::wifi::STA_CONNECTED_AT
+                .borrow_ref_mut(cs)
+                .replace(crate::current_millis());
+        });
+    }
+
     memory_fence();
 
+    #[allow(non_upper_case_globals)]
+    let event = match event_id as u32 {
+        wifi_event_t_WIFI_EVENT_STA_START => Some(WifiEvent::StaStart),
+        wifi_event_t_WIFI_EVENT_STA_STOP => Some(WifiEvent::StaStop),
+        wifi_event_t_WIFI_EVENT_STA_CONNECTED => Some(WifiEvent::StaConnected),
+        wifi_event_t_WIFI_EVENT_STA_DISCONNECTED => {
+            let reason = if event_data.is_null() {
+                DisconnectReason::Unspecified
+            } else {
+                DisconnectReason::from_raw(
+                    (*(event_data as *const wifi_event_sta_disconnected_t)).reason,
+                )
+            };
+            critical_section::with(|cs| {
+                crate::wifi::LAST_DISCONNECT_REASON
+                    .borrow_ref_mut(cs)
+                    .replace(reason);
+            });
+            Some(WifiEvent::StaDisconnected { reason })
+        }
+        wifi_event_t_WIFI_EVENT_STA_BSS_RSSI_LOW if !event_data.is_null() => {
+            let rssi = (*(event_data as *const wifi_event_bss_rssi_low_t)).rssi;
+            Some(WifiEvent::StaBssRssiLow { rssi: rssi as i8 })
+        }
+        wifi_event_t_WIFI_EVENT_SCAN_DONE => Some(WifiEvent::ScanDone),
+        wifi_event_t_WIFI_EVENT_AP_START => Some(WifiEvent::ApStart),
+        wifi_event_t_WIFI_EVENT_AP_STOP => Some(WifiEvent::ApStop),
+        _ => None,
+    };
+
+    if let Some(event) = event {
+        crate::wifi::emit_event(event);
+    }
+
     0
 }
 
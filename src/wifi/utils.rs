@@ -1,31 +1,43 @@
 use smoltcp::{
     iface::{Interface, InterfaceBuilder, Neighbor, NeighborCache, Route, Routes, SocketStorage},
-    socket::{Dhcpv4Socket, TcpSocket, TcpSocketBuffer},
-    wire::{EthernetAddress, IpAddress, IpCidr},
+    socket::{
+        Dhcpv4Socket, TcpSocket, TcpSocketBuffer, UdpPacketMetadata, UdpSocket, UdpSocketBuffer,
+    },
+    wire::{EthernetAddress, IpAddress, IpCidr, Ipv4Address},
 };
 
 use crate::wifi::get_sta_mac;
 
 use super::WifiDevice;
 
+/// Create the storage for `create_network_interface`.
+///
+/// `$socket_count` counts the TCP sockets and the DHCP socket, the slot of the UDP socket is
+/// added on top of it.
 #[macro_export]
 macro_rules! create_network_stack_storage {
     ($socket_count:literal , $cache_count:literal , $routes_count:literal) => {{
         use smoltcp::iface::{Neighbor, NeighborCache, Route, SocketStorage};
         use smoltcp::wire::{IpAddress, IpCidr, Ipv4Address};
 
-        let mut socket_set_entries: [SocketStorage; $socket_count] = Default::default();
+        // one more slot for the UDP socket
+        let mut socket_set_entries: [SocketStorage; $socket_count + 1] =
+            [SocketStorage::EMPTY; $socket_count + 1];
         let mut neighbor_cache_storage: [Option<(IpAddress, Neighbor)>; $cache_count] =
             Default::default();
         let mut routes_storage: [Option<(IpCidr, Route)>; $routes_count] = Default::default();
         let ip_addr = IpCidr::new(Ipv4Address::UNSPECIFIED.into(), 0);
         let mut ip_addrs = [ip_addr];
+        // the DHCP socket doesn't need TCP buffers
+        let mut tcp_socket_storage = [$crate::wifi::utils::TcpSocketStorage::new();
+            ($socket_count as usize).saturating_sub(1)];
 
         (
             socket_set_entries,
             neighbor_cache_storage,
             routes_storage,
             ip_addrs,
+            tcp_socket_storage,
         )
     }};
 }
@@ -33,24 +45,55 @@ macro_rules! create_network_stack_storage {
 #[macro_export]
 macro_rules! network_stack_storage {
     ($param:ident) => {{
-        (&mut $param.0, &mut $param.1, &mut $param.2, &mut $param.3)
+        (
+            &mut $param.0,
+            &mut $param.1,
+            &mut $param.2,
+            &mut $param.3,
+            &mut $param.4,
+        )
     }};
 }
 
+/// Receive and transmit buffers of one TCP socket created by `create_network_interface`.
+#[derive(Clone, Copy)]
+pub struct TcpSocketStorage {
+    rx: [u8; 1536],
+    tx: [u8; 1536],
+}
+
+impl TcpSocketStorage {
+    pub const fn new() -> TcpSocketStorage {
+        TcpSocketStorage {
+            rx: [0; 1536],
+            tx: [0; 1536],
+        }
+    }
+}
+
 /// Convenient way to create an `smoltcp` ethernet interface
 /// You can use the provided macros to create and pass a suitable backing storage.
+///
+/// The interface gets a DHCP socket, an UDP socket (used e.g. for DNS lookups) and
+/// TCP sockets in all the remaining socket slots. The storage needs at least two socket slots
+/// for the DHCP and the UDP socket, e.g. three slots result in a single TCP socket.
+/// `create_network_stack_storage!` adds the slot of the UDP socket by itself.
+/// Every TCP socket gets its own buffers from the `TcpSocketStorage`s, a slot without one is
+/// left empty.
 pub fn create_network_interface<'a>(
     storage: (
         &'a mut [SocketStorage<'a>],
         &'a mut [Option<(IpAddress, Neighbor)>],
         &'a mut [Option<(IpCidr, Route)>],
         &'a mut [IpCidr; 1],
+        &'a mut [TcpSocketStorage],
     ),
 ) -> Interface<WifiDevice> {
     let socket_set_entries = storage.0;
     let neighbor_cache_storage = storage.1;
     let routes_storage = storage.2;
     let ip_addrs = storage.3;
+    let tcp_socket_storage = storage.4;
 
     let mut mac = [0u8; 6];
     get_sta_mac(&mut mac);
@@ -61,26 +104,38 @@ pub fn create_network_interface<'a>(
     let neighbor_cache = NeighborCache::new(&mut neighbor_cache_storage[..]);
     let routes = Routes::new(&mut routes_storage[..]);
 
-    let sockets_to_add = socket_set_entries.len() - 1;
+    // without storage for the groups joining a multicast group fails
+    static mut MULTICAST_GROUPS: [Option<(Ipv4Address, ())>; 4] = [None; 4];
+
+    let sockets_to_add = socket_set_entries.len().saturating_sub(2);
     let mut ethernet = InterfaceBuilder::new(device, socket_set_entries)
         .hardware_addr(smoltcp::wire::HardwareAddress::Ethernet(hw_address))
         .neighbor_cache(neighbor_cache)
         .ip_addrs(&mut ip_addrs[..])
         .routes(routes)
+        .ipv4_multicast_groups(unsafe { &mut MULTICAST_GROUPS[..] })
         .finalize();
 
-    for _ in 0..sockets_to_add {
-        let rx_tx_socket1 = {
-            static mut TCP_SERVER_RX_DATA: [u8; 1536] = [0; 1536];
-            static mut TCP_SERVER_TX_DATA: [u8; 1536] = [0; 1536];
+    for buffers in tcp_socket_storage.iter_mut().take(sockets_to_add) {
+        let tcp_rx_buffer = TcpSocketBuffer::new(&mut buffers.rx[..]);
+        let tcp_tx_buffer = TcpSocketBuffer::new(&mut buffers.tx[..]);
+        ethernet.add_socket(TcpSocket::new(tcp_rx_buffer, tcp_tx_buffer));
+    }
 
-            let tcp_rx_buffer = unsafe { TcpSocketBuffer::new(&mut TCP_SERVER_RX_DATA[..]) };
-            let tcp_tx_buffer = unsafe { TcpSocketBuffer::new(&mut TCP_SERVER_TX_DATA[..]) };
+    let udp_socket = {
+        static mut UDP_RX_METADATA: [UdpPacketMetadata; 4] = [UdpPacketMetadata::EMPTY; 4];
+        static mut UDP_RX_DATA: [u8; 1536] = [0; 1536];
+        static mut UDP_TX_METADATA: [UdpPacketMetadata; 4] = [UdpPacketMetadata::EMPTY; 4];
+        static mut UDP_TX_DATA: [u8; 1536] = [0; 1536];
 
-            TcpSocket::new(tcp_rx_buffer, tcp_tx_buffer)
-        };
-        ethernet.add_socket(rx_tx_socket1);
-    }
+        let udp_rx_buffer =
+            unsafe { UdpSocketBuffer::new(&mut UDP_RX_METADATA[..], &mut UDP_RX_DATA[..]) };
+        let udp_tx_buffer =
+            unsafe { UdpSocketBuffer::new(&mut UDP_TX_METADATA[..], &mut UDP_TX_DATA[..]) };
+
+        UdpSocket::new(udp_rx_buffer, udp_tx_buffer)
+    };
+    ethernet.add_socket(udp_socket);
 
     let dhcp_socket = Dhcpv4Socket::new();
     ethernet.add_socket(dhcp_socket);
@@ -7,18 +7,43 @@ use embedded_svc::ipv4::Ipv4Addr;
 use embedded_svc::{
     ipv4::{ClientSettings, Mask, Subnet},
     wifi::{
-        AccessPointInfo, ApStatus, AuthMethod, ClientConnectionStatus, ClientIpStatus,
-        ClientStatus, SecondaryChannel, Status,
+        AccessPointInfo, ApIpStatus, ApStatus, AuthMethod, ClientConnectionStatus, ClientIpStatus,
+        ClientStatus, Protocol, SecondaryChannel, Status,
     },
 };
 use enumset::EnumSet;
 use smoltcp::iface::{Interface, SocketHandle};
-use smoltcp::socket::{Dhcpv4Socket, TcpSocket};
-use smoltcp::time::Instant;
-use smoltcp::wire::{IpAddress, IpCidr, Ipv4Address};
+use smoltcp::socket::{
+    AnySocket, Dhcpv4Socket, IcmpEndpoint, IcmpPacketMetadata, IcmpSocket, IcmpSocketBuffer,
+    RawSocket as SmolRawSocket, RawSocketBuffer, TcpSocket, TcpState, UdpSocket as SmolUdpSocket,
+};
+use smoltcp::time::{Duration, Instant};
+use smoltcp::wire::{
+    EthernetAddress, HardwareAddress, Icmpv4Packet, Icmpv4Repr, IpAddress, IpCidr, IpEndpoint,
+    IpProtocol, IpVersion, Ipv4Address, Ipv4Cidr, Ipv4Packet, Ipv4Repr,
+};
 
 use crate::current_millis;
-use crate::wifi::WifiDevice;
+pub use dns::DnsError;
+use mdns::MdnsError;
+use sntp::SntpError;
+
+#[cfg(feature = "async")]
+pub mod asynch;
+pub mod credentials;
+mod dns;
+pub mod event_bus;
+pub mod mdns;
+#[cfg(feature = "embedded-nal")]
+pub mod nal;
+pub mod sntp;
+#[cfg(feature = "tls")]
+pub mod tls;
+use crate::wifi::{
+    ApRecord, Bandwidth, Country, CountryPolicy, CsiConfig, CsiData, DataFrame, DeviceStats,
+    DisconnectReason, EnterpriseConfig, FrameKind, PowerSaveMode, ScanConfig, SnifferFilter,
+    WifiDevice, WifiEvent, WifiInterface,
+};
 
 /// An implementation of `embedded-svc`'s wifi trait.
 pub struct Wifi<'a> {
@@ -26,6 +51,27 @@ pub struct Wifi<'a> {
     current_config: embedded_svc::wifi::Configuration,
     network_config: Option<smoltcp::socket::Dhcpv4Config>,
     dhcp_socket_handle: Option<SocketHandle>,
+    static_ip: bool,
+    connect_started_at: Option<u64>,
+    got_ip_at: Option<u64>,
+    ip_watchdog_timeout: Option<u64>,
+    ip_lost_since: Option<u64>,
+    ip_watchdog_renewed: bool,
+    auto_reconnect: Option<AutoReconnect>,
+    user_disconnected: bool,
+    connect_retries: Option<u8>,
+    connect_retries_left: Option<u8>,
+    dhcp_events: heapless::Deque<DhcpEvent, 4>,
+    last_lease_at: Option<u64>,
+    dns_servers: Option<[Option<Ipv4Address>; 2]>,
+}
+
+const INITIAL_RECONNECT_BACKOFF_MS: u64 = 1000;
+
+struct AutoReconnect {
+    max_backoff_ms: u64,
+    backoff_ms: u64,
+    next_attempt_at: Option<u64>,
 }
 
 impl<'a> Wifi<'a> {
@@ -45,6 +91,19 @@ impl<'a> Wifi<'a> {
             current_config: embedded_svc::wifi::Configuration::default(),
             network_config: None,
             dhcp_socket_handle,
+            static_ip: false,
+            connect_started_at: None,
+            got_ip_at: None,
+            ip_watchdog_timeout: None,
+            ip_lost_since: None,
+            ip_watchdog_renewed: false,
+            auto_reconnect: None,
+            user_disconnected: false,
+            connect_retries: None,
+            connect_retries_left: None,
+            dhcp_events: heapless::Deque::new(),
+            last_lease_at: None,
+            dns_servers: None,
         }
     }
 
@@ -54,75 +113,1805 @@ impl<'a> Wifi<'a> {
     }
 
     /// Convenience function to poll the DHCP socket.
+    /// The DHCP socket isn't polled while the network stack is bound to the soft-AP or a static IP is used.
+    ///
+    /// This also drives the IP watchdog and the automatic reconnect.
     pub fn poll_dhcp(&mut self) -> Result<(), WifiError> {
+        if !crate::wifi::is_ap_data_interface() && !self.static_ip {
+            self.poll_dhcp_socket()?;
+        }
+
+        self.check_ip_watchdog();
+        self.check_connect_retry();
+        self.check_auto_reconnect();
+
+        Ok(())
+    }
+
+    fn poll_dhcp_socket(&mut self) -> Result<(), WifiError> {
         if let Some(dhcp_handle) = self.dhcp_socket_handle {
             let dhcp_socket = self
                 .network_interface
                 .get_socket::<Dhcpv4Socket>(dhcp_handle);
             let event = dhcp_socket.poll();
-            if let Some(event) = event {
-                match event {
-                    smoltcp::socket::Dhcpv4Event::Deconfigured => {
-                        self.network_config = None;
-                        self.network_interface
-                            .routes_mut()
-                            .remove_default_ipv4_route();
-                    }
-                    smoltcp::socket::Dhcpv4Event::Configured(config) => {
-                        self.network_config = Some(config);
-                        let address = config.address;
-                        self.network_interface.update_ip_addrs(|addrs| {
-                            let addr = addrs
-                                .iter_mut()
-                                .filter(|cidr| match cidr.address() {
-                                    IpAddress::Ipv4(_) => true,
-                                    _ => false,
-                                })
-                                .next()
-                                .unwrap();
-
-                            *addr = IpCidr::Ipv4(address);
-                        });
-                        if let Some(route) = config.router {
-                            self.network_interface
-                                .routes_mut()
-                                .add_default_ipv4_route(route)?;
-                        }
+            let lease = critical_section::with(|cs| *crate::wifi::DHCP_LEASE.borrow_ref(cs));
+            let lease_time = lease.map(|(lease_time, _)| Duration::from_secs(lease_time as u64));
+            let new_lease = lease.map(|(_, acquired_at)| acquired_at) != self.last_lease_at;
+            self.last_lease_at = lease.map(|(_, acquired_at)| acquired_at);
+
+            match event {
+                Some(smoltcp::socket::Dhcpv4Event::Deconfigured) => {
+                    self.network_config = None;
+                    self.network_interface
+                        .routes_mut()
+                        .remove_default_ipv4_route();
+                    self.push_dhcp_event(DhcpEvent::Deconfigured);
+                }
+                Some(smoltcp::socket::Dhcpv4Event::Configured(config)) => {
+                    if self.connect_started_at.is_some() && self.got_ip_at.is_none() {
+                        self.got_ip_at = Some(current_millis());
                     }
+                    self.apply_ipv4_config(config)?;
+                    crate::wifi::emit_event(WifiEvent::GotIp);
+                    self.push_dhcp_event(DhcpEvent::Configured {
+                        address: config.address,
+                        router: config.router,
+                        dns_servers: config.dns_servers,
+                        lease_time,
+                    });
+                }
+                None if new_lease && self.network_config.is_some() => {
+                    self.push_dhcp_event(DhcpEvent::Renewed { lease_time });
+                }
+                None => (),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Queue a DHCP event, the oldest one is dropped if the queue is full.
+    fn push_dhcp_event(&mut self, event: DhcpEvent) {
+        if self.dhcp_events.is_full() {
+            self.dhcp_events.pop_front();
+        }
+        // can't fail, there is space now
+        self.dhcp_events.push_back(event).ok();
+    }
+
+    /// Take the oldest DHCP event which happened while polling the DHCP socket.
+    ///
+    /// Up to four events are kept, older ones are dropped.
+    pub fn take_dhcp_event(&mut self) -> Option<DhcpEvent> {
+        self.dhcp_events.pop_front()
+    }
+
+    /// Automatically reconnect when the connection to the AP is lost.
+    ///
+    /// Reconnecting is retried with an exponential backoff starting at one second and
+    /// capped at `max_backoff_ms`. Calling `disconnect` stops reconnecting until `connect` is called.
+    /// Reconnecting is driven by `poll_dhcp`.
+    pub fn enable_auto_reconnect(&mut self, max_backoff_ms: u64) {
+        self.auto_reconnect = Some(AutoReconnect {
+            max_backoff_ms,
+            backoff_ms: INITIAL_RECONNECT_BACKOFF_MS.min(max_backoff_ms),
+            next_attempt_at: None,
+        });
+    }
+
+    /// Stop reconnecting automatically.
+    pub fn disable_auto_reconnect(&mut self) {
+        self.auto_reconnect = None;
+    }
+
+    /// Retry connecting up to `retries` times if connecting fails right away.
+    ///
+    /// Only failures which might go away when trying again, e.g. the AP wasn't found or was
+    /// busy, are retried. A wrong password isn't. Once all the retries failed the reason of the
+    /// last failure is kept in `last_disconnect_reason`, `connect_blocking` returns it as
+    /// `WifiError::ConnectFailed`. This only applies to the attempts started by `connect`,
+    /// losing the connection later on is handled by `enable_auto_reconnect`.
+    /// Retrying is driven by `poll_dhcp`.
+    pub fn set_connect_retries(&mut self, retries: u8) {
+        self.connect_retries = Some(retries);
+    }
+
+    /// Retry a failed connect, returns the reason of the last failure once giving up.
+    fn check_connect_retry(&mut self) -> Option<DisconnectReason> {
+        let retries_left = self.connect_retries_left?;
+
+        match crate::wifi::get_wifi_state() {
+            crate::wifi::WifiState::StaConnected => {
+                self.connect_retries_left = None;
+                None
+            }
+            crate::wifi::WifiState::StaDisconnected => {
+                let reason = self.last_disconnect_reason()?;
+                let retryable = matches!(
+                    reason,
+                    DisconnectReason::NoApFound
+                        | DisconnectReason::AssocFail
+                        | DisconnectReason::AssocTooMany
+                        | DisconnectReason::ConnectionFail
+                );
+
+                if !retryable || retries_left == 0 {
+                    self.connect_retries_left = None;
+                    return Some(reason);
+                }
+
+                critical_section::with(|cs| {
+                    crate::wifi::LAST_DISCONNECT_REASON
+                        .borrow_ref_mut(cs)
+                        .take()
+                });
+                log::info!("connecting failed ({:?}), retrying", reason);
+                self.connect_retries_left = Some(retries_left - 1);
+                crate::wifi::wifi_connect();
+                None
+            }
+            _ => None,
+        }
+    }
+
+    fn check_auto_reconnect(&mut self) {
+        // the initial attempts are covered by the connect retries
+        if self.user_disconnected || self.connect_retries_left.is_some() {
+            return;
+        }
+
+        let reconnect = match &mut self.auto_reconnect {
+            Some(reconnect) => reconnect,
+            None => return,
+        };
+
+        match crate::wifi::get_wifi_state() {
+            crate::wifi::WifiState::StaConnected => {
+                reconnect.backoff_ms = INITIAL_RECONNECT_BACKOFF_MS.min(reconnect.max_backoff_ms);
+                reconnect.next_attempt_at = None;
+            }
+            crate::wifi::WifiState::StaDisconnected => {
+                let now = current_millis();
+                let next_attempt_at = *reconnect
+                    .next_attempt_at
+                    .get_or_insert(now + reconnect.backoff_ms);
+
+                if now >= next_attempt_at {
+                    log::info!("reconnecting");
+                    crate::wifi::wifi_connect();
+                    reconnect.backoff_ms = (reconnect.backoff_ms * 2).min(reconnect.max_backoff_ms);
+                    reconnect.next_attempt_at = Some(now + reconnect.backoff_ms);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Use a static IPv4 configuration instead of DHCP.
+    ///
+    /// From now on `poll_dhcp` doesn't touch the configuration anymore until `clear_static_ip` is called.
+    pub fn set_static_ip(
+        &mut self,
+        ip: Ipv4Cidr,
+        gateway: Ipv4Address,
+        dns: Option<Ipv4Address>,
+    ) -> Result<(), WifiError> {
+        self.static_ip = true;
+        self.apply_ipv4_config(smoltcp::socket::Dhcpv4Config {
+            address: ip,
+            router: Some(gateway),
+            dns_servers: [dns, None, None],
+        })
+    }
+
+    /// Go back to getting the IPv4 configuration via DHCP.
+    pub fn clear_static_ip(&mut self) {
+        if !self.static_ip {
+            return;
+        }
+
+        self.static_ip = false;
+        self.reset_ipv4_config();
+    }
+
+    /// Drop the current DHCP lease and start over with a DHCP discover on the next `poll_dhcp`.
+    ///
+    /// Useful when the address might be stale, e.g. after waking up from deep sleep.
+    /// The lease isn't released on the server since smoltcp can't send a DHCP release.
+    /// Does nothing while a static IP is used.
+    pub fn dhcp_reset(&mut self) {
+        if self.static_ip {
+            return;
+        }
+
+        let was_configured = self.network_config.is_some();
+        self.reset_ipv4_config();
+        critical_section::with(|cs| crate::wifi::DHCP_LEASE.borrow_ref_mut(cs).take());
+        self.last_lease_at = None;
+
+        if was_configured {
+            self.push_dhcp_event(DhcpEvent::Deconfigured);
+        }
+    }
+
+    /// Set the hostname sent to the DHCP server (option 12).
+    ///
+    /// The hostname is included in every DHCP message sent from now on, so set it before
+    /// connecting. It has to be 1 to 32 characters long, consist of ASCII letters, digits
+    /// and hyphens and must not start or end with a hyphen.
+    pub fn set_hostname(&mut self, name: &str) -> Result<(), WifiError> {
+        let valid_chars = name.bytes().all(|c| c.is_ascii_alphanumeric() || c == b'-');
+        if !valid_chars || name.starts_with('-') || name.ends_with('-') {
+            return Err(WifiError::InvalidArgument);
+        }
+
+        let mut hostname = heapless::String::new();
+        hostname
+            .push_str(name)
+            .map_err(|_| WifiError::InvalidArgument)?;
+        if hostname.is_empty() {
+            return Err(WifiError::InvalidArgument);
+        }
+
+        crate::wifi::set_dhcp_hostname(Some(hostname));
+        Ok(())
+    }
+
+    /// Forget the current IPv4 configuration and restart DHCP.
+    fn reset_ipv4_config(&mut self) {
+        self.network_config = None;
+        self.network_interface
+            .routes_mut()
+            .remove_default_ipv4_route();
+        self.network_interface.update_ip_addrs(|addrs| {
+            for addr in addrs.iter_mut() {
+                if let IpAddress::Ipv4(_) = addr.address() {
+                    *addr = IpCidr::new(Ipv4Address::UNSPECIFIED.into(), 0);
                 }
             }
+        });
+
+        if let Some(dhcp_handle) = self.dhcp_socket_handle {
+            self.network_interface
+                .get_socket::<Dhcpv4Socket>(dhcp_handle)
+                .reset();
+        }
+    }
+
+    fn apply_ipv4_config(
+        &mut self,
+        config: smoltcp::socket::Dhcpv4Config,
+    ) -> Result<(), WifiError> {
+        self.network_config = Some(config);
+        let address = config.address;
+        self.network_interface.update_ip_addrs(|addrs| {
+            let addr = addrs
+                .iter_mut()
+                .filter(|cidr| match cidr.address() {
+                    IpAddress::Ipv4(_) => true,
+                    _ => false,
+                })
+                .next()
+                .unwrap();
+
+            *addr = IpCidr::Ipv4(address);
+        });
+        if let Some(route) = config.router {
+            self.network_interface
+                .routes_mut()
+                .add_default_ipv4_route(route)?;
+        }
+
+        Ok(())
+    }
+
+    /// Enable or disable (`None`) the watchdog recovering a connection without an IP address.
+    ///
+    /// When the station is connected to an AP but has no IPv4 address for longer than
+    /// `timeout_ms` the DHCP client is restarted. If there is still no address after another
+    /// `timeout_ms` the station reconnects to the AP.
+    /// The watchdog is driven by `poll_dhcp`.
+    pub fn set_ip_watchdog(&mut self, timeout_ms: Option<u64>) {
+        self.ip_watchdog_timeout = timeout_ms;
+        self.ip_lost_since = None;
+        self.ip_watchdog_renewed = false;
+    }
+
+    fn check_ip_watchdog(&mut self) {
+        let timeout = match self.ip_watchdog_timeout {
+            Some(timeout) => timeout,
+            None => return,
+        };
+
+        let connected = matches!(
+            crate::wifi::get_wifi_state(),
+            crate::wifi::WifiState::StaConnected
+        );
+        if !connected || self.network_config.is_some() {
+            self.ip_lost_since = None;
+            self.ip_watchdog_renewed = false;
+            return;
+        }
+
+        let now = current_millis();
+        let lost_since = *self.ip_lost_since.get_or_insert(now);
+        let elapsed = now - lost_since;
+
+        if elapsed >= 2 * timeout {
+            log::warn!("no IP address for {} ms, reconnecting", elapsed);
+            crate::wifi::wifi_disconnect();
+            crate::wifi::wifi_connect();
+            self.ip_lost_since = None;
+            self.ip_watchdog_renewed = false;
+        } else if elapsed >= timeout && !self.ip_watchdog_renewed {
+            log::warn!("no IP address for {} ms, restarting DHCP", elapsed);
+            if let Some(dhcp_handle) = self.dhcp_socket_handle {
+                self.network_interface
+                    .get_socket::<Dhcpv4Socket>(dhcp_handle)
+                    .reset();
+            }
+            self.ip_watchdog_renewed = true;
+        }
+    }
+
+    /// Get the time left until the current DHCP lease expires.
+    ///
+    /// Returns `None` if no address was assigned via DHCP or the server didn't send a lease time.
+    pub fn dhcp_lease_remaining(&self) -> Option<Duration> {
+        if self.network_config.is_none() || self.static_ip {
+            return None;
+        }
+
+        let (lease_time, acquired_at) =
+            critical_section::with(|cs| *crate::wifi::DHCP_LEASE.borrow_ref(cs))?;
+        let expires_at = acquired_at + lease_time as u64 * 1000;
+        Some(Duration::from_millis(
+            expires_at.saturating_sub(current_millis()),
+        ))
+    }
+
+    /// Get how far DHCP got in obtaining an address.
+    ///
+    /// `Renewing` is reported once half of the lease time has passed, that's when the
+    /// lease is renewed.
+    pub fn dhcp_state(&self) -> DhcpState {
+        if crate::wifi::is_ap_data_interface() || self.static_ip {
+            return DhcpState::Disabled;
+        }
+
+        if self.network_config.is_none() {
+            return DhcpState::Discovering;
+        }
+
+        let lease = critical_section::with(|cs| *crate::wifi::DHCP_LEASE.borrow_ref(cs));
+        match lease {
+            Some((lease_time, acquired_at))
+                if current_millis() >= acquired_at + lease_time as u64 * 1000 / 2 =>
+            {
+                DhcpState::Renewing
+            }
+            _ => DhcpState::Bound,
+        }
+    }
+
+    /// Get the address assigned via DHCP together with the timers of its lease.
+    ///
+    /// The lease is renewed once half of its time has passed. Returns `None` if no address
+    /// is assigned or DHCP isn't used.
+    pub fn dhcp_lease_info(&self) -> Option<DhcpLease> {
+        if crate::wifi::is_ap_data_interface() || self.static_ip {
+            return None;
+        }
+
+        let config = self.network_config?;
+        let lease = critical_section::with(|cs| *crate::wifi::DHCP_LEASE.borrow_ref(cs));
+        let now = current_millis();
+        let remaining = |at: u64| Duration::from_millis(at.saturating_sub(now));
+
+        Some(DhcpLease {
+            address: config.address,
+            lease_time: lease.map(|(lease_time, _)| Duration::from_secs(lease_time as u64)),
+            renew_in: lease.map(|(lease_time, acquired_at)| {
+                remaining(acquired_at + lease_time as u64 * 1000 / 2)
+            }),
+            expires_in: lease
+                .map(|(lease_time, acquired_at)| remaining(acquired_at + lease_time as u64 * 1000)),
+        })
+    }
+
+    /// Poll the DHCP socket (see `poll_dhcp`) until an address is assigned.
+    ///
+    /// Returns `nb::Error::WouldBlock` while DHCP is still discovering, so it can be used
+    /// with `nb::block!`. It succeeds right away if DHCP isn't used.
+    pub fn poll_dhcp_bound(&mut self) -> nb::Result<DhcpState, WifiError> {
+        self.poll_dhcp()?;
+
+        match self.dhcp_state() {
+            DhcpState::Discovering => Err(nb::Error::WouldBlock),
+            state => Ok(state),
+        }
+    }
+
+    /// Get the SSID and password of the station configuration stored in the driver.
+    ///
+    /// With flash storage this might be a configuration persisted before the last reboot.
+    /// Returns `None` if there is no stored SSID.
+    pub fn stored_credentials(&self) -> Option<(heapless::String<32>, heapless::String<64>)> {
+        let mut cfg: crate::binary::include::wifi_config_t = unsafe { core::mem::zeroed() };
+        let res = unsafe {
+            crate::binary::include::esp_wifi_get_config(
+                crate::binary::include::wifi_interface_t_WIFI_IF_STA,
+                &mut cfg,
+            )
+        };
+        if res != 0 {
+            log::warn!("esp_wifi_get_config failed: {}", res);
+            return None;
+        }
+
+        let (ssid, password) = unsafe { (cfg.sta.ssid, cfg.sta.password) };
+        let ssid = str_from_raw::<32>(&ssid);
+        if ssid.is_empty() {
+            return None;
+        }
+
+        Some((ssid, str_from_raw::<64>(&password)))
+    }
+
+    /// Forget the current network.
+    ///
+    /// This clears the station credentials held by the driver (including the copy persisted
+    /// in NVS when flash storage is used) and resets the cached configuration.
+    pub fn forget(&mut self) -> Result<(), WifiError> {
+        let res = crate::wifi::wifi_clear_sta_config();
+        if res != 0 {
+            return Err(WifiError::from_raw(res));
+        }
+
+        self.current_config = embedded_svc::wifi::Configuration::default();
+        Ok(())
+    }
+
+    /// Get the channel and bandwidth currently used by the station.
+    pub fn current_channel_info(&self) -> Result<ChannelInfo, WifiError> {
+        let mut primary = 0u8;
+        let mut second = crate::binary::include::wifi_second_chan_t_WIFI_SECOND_CHAN_NONE;
+        let mut bw = crate::binary::include::wifi_bandwidth_t_WIFI_BW_HT20;
+
+        unsafe {
+            let res = crate::binary::include::esp_wifi_get_channel(&mut primary, &mut second);
+            if res != 0 {
+                return Err(WifiError::from_raw(res));
+            }
+
+            let res = crate::binary::include::esp_wifi_get_bandwidth(
+                crate::binary::include::wifi_interface_t_WIFI_IF_STA,
+                &mut bw,
+            );
+            if res != 0 {
+                return Err(WifiError::from_raw(res));
+            }
+        }
+
+        let bandwidth = Bandwidth::from_raw(bw);
+        let secondary = secondary_channel_from_raw(second);
+
+        let primary_frequency = channel_to_frequency(primary);
+        let center_frequency = match (bandwidth, secondary) {
+            (Bandwidth::Ht40, SecondaryChannel::Above) => primary_frequency + 10,
+            (Bandwidth::Ht40, SecondaryChannel::Below) => primary_frequency - 10,
+            _ => primary_frequency,
+        };
+
+        Ok(ChannelInfo {
+            primary,
+            secondary,
+            bandwidth,
+            center_frequency,
+        })
+    }
+
+    /// Run a blocking scan and convert up to `N` records from the driver using `convert`.
+    fn scan_records<T, const N: usize>(
+        &mut self,
+        config: &ScanConfig,
+        convert: fn(&crate::binary::include::wifi_ap_record_t) -> T,
+    ) -> Result<(heapless::Vec<T, N>, usize), WifiError> {
+        if config.ssid.map_or(false, |ssid| ssid.len() > 32)
+            || config
+                .channel
+                .map_or(false, |channel| !(1..=14).contains(&channel))
+        {
+            return Err(WifiError::InvalidArgument);
+        }
+
+        let (active_min, active_max) = config.active_time_ms();
+        if active_max < active_min {
+            return Err(WifiError::InvalidArgument);
+        }
+
+        let res = crate::wifi::wifi_start_scan(config);
+        if res != 0 {
+            return Err(WifiError::from_raw(res));
+        }
+
+        Ok(self.fetch_scan_records(convert))
+    }
+
+    /// A blocking wifi network scan restricted by `config`.
+    ///
+    /// Scanning a single channel is a lot faster than scanning all of them.
+    /// The returned records are the same as the ones of `scan_n`.
+    ///
+    /// APs with a hidden SSID are included with an empty `ssid` if `show_hidden` is set.
+    /// Since an SSID which isn't valid UTF-8 can end up empty, too, use `scan_raw_with_config`
+    /// and `ApRecord::is_hidden` to tell them apart reliably.
+    pub fn scan_with_config<const N: usize>(
+        &mut self,
+        config: ScanConfig,
+    ) -> Result<(heapless::Vec<AccessPointInfo, N>, usize), WifiError> {
+        self.scan_records(&config, access_point_info_from_raw)
+    }
+
+    /// A blocking scan of the single `channel`, e.g. to look for a better AP to roam to.
+    ///
+    /// While connected the station is off its channel only for the time an active scan spends
+    /// on one channel, 20 ms by default. The association is kept. Use `scan_with_config` to
+    /// filter by SSID or shorten the time per channel.
+    pub fn scan_channel<const N: usize>(
+        &mut self,
+        channel: u8,
+    ) -> Result<(heapless::Vec<AccessPointInfo, N>, usize), WifiError> {
+        self.scan_with_config(ScanConfig {
+            channel: Some(channel),
+            ..Default::default()
+        })
+    }
+
+    /// Fetch up to `N` records of the last scan from the driver and convert them using `convert`.
+    ///
+    /// The driver keeps all the APs found during the scan on the heap and there is no way to cap
+    /// the number of records it retains. Fetching the records releases that memory,
+    /// the list is also cleared if fetching fails so it doesn't linger until the next scan.
+    ///
+    /// The raw records are fetched into a temporary heap buffer, with a large `N` they
+    /// would take up a lot of stack.
+    fn fetch_scan_records<T, const N: usize>(
+        &mut self,
+        convert: fn(&crate::binary::include::wifi_ap_record_t) -> T,
+    ) -> (heapless::Vec<T, N>, usize) {
+        let mut scanned = heapless::Vec::new();
+        let mut bss_total: u16 = 0;
+
+        unsafe {
+            crate::binary::include::esp_wifi_scan_get_ap_num(&mut bss_total);
+            if bss_total as usize > N {
+                bss_total = N as u16;
+            }
+
+            let records = if bss_total > 0 {
+                crate::compat::malloc::malloc(
+                    (bss_total as usize
+                        * core::mem::size_of::<crate::binary::include::wifi_ap_record_t>())
+                        as u32,
+                ) as *mut crate::binary::include::wifi_ap_record_t
+            } else {
+                core::ptr::null_mut()
+            };
+
+            if records.is_null() {
+                crate::binary::include::esp_wifi_clear_ap_list();
+                return (scanned, 0);
+            }
+
+            let res = crate::binary::include::esp_wifi_scan_get_ap_records(&mut bss_total, records);
+            if res != 0 {
+                crate::binary::include::esp_wifi_clear_ap_list();
+                bss_total = 0;
+            }
+
+            for record in core::slice::from_raw_parts(records, bss_total as usize) {
+                // can't fail, there are at most `N` records
+                scanned.push(convert(record)).ok();
+            }
+
+            crate::compat::malloc::free(records as *const u8);
+        }
+
+        (scanned, bss_total as usize)
+    }
+
+    /// Start a scan without waiting for it to finish.
+    ///
+    /// Use `poll_scan_results` to get the results once the scan is done.
+    pub fn begin_scan(&mut self) -> Result<(), WifiError> {
+        let res = crate::wifi::wifi_begin_scan(&ScanConfig::default());
+        if res != 0 {
+            return Err(WifiError::from_raw(res));
+        }
+
+        Ok(())
+    }
+
+    /// Get the results of a scan started by `begin_scan`.
+    ///
+    /// Returns `None` until the driver reports the scan to be done, afterwards the records are
+    /// fetched like `scan_n` does.
+    pub fn poll_scan_results<const N: usize>(
+        &mut self,
+    ) -> Option<Result<(heapless::Vec<AccessPointInfo, N>, usize), WifiError>> {
+        if !crate::wifi::take_scan_done() {
+            return None;
+        }
+
+        Some(Ok(self.fetch_scan_records(access_point_info_from_raw)))
+    }
+
+    /// A blocking wifi network scan returning all the information the driver has about the APs.
+    ///
+    /// Unlike `scan_n` this includes details `AccessPointInfo` can't represent like the
+    /// cipher suites and WPS support.
+    pub fn scan_raw_n<const N: usize>(
+        &mut self,
+    ) -> Result<(heapless::Vec<ApRecord, N>, usize), WifiError> {
+        self.scan_raw_with_config(ScanConfig::default())
+    }
+
+    /// Like `scan_raw_n` but restricted by `config`, see `scan_with_config`.
+    pub fn scan_raw_with_config<const N: usize>(
+        &mut self,
+        config: ScanConfig,
+    ) -> Result<(heapless::Vec<ApRecord, N>, usize), WifiError> {
+        self.scan_records(&config, ApRecord::from_raw)
+    }
+
+    /// The DNS server to use for lookups.
+    pub(crate) fn dns_server(&self) -> Option<Ipv4Address> {
+        self.dns_servers()[0]
+    }
+
+    /// The primary and secondary DNS server, the ones set via `set_dns_servers` take precedence.
+    fn dns_servers(&self) -> [Option<Ipv4Address>; 2] {
+        match (self.dns_servers, self.network_config) {
+            (Some(dns_servers), _) => dns_servers,
+            (None, Some(config)) => [config.dns_servers[0], config.dns_servers[1]],
+            (None, None) => [None, None],
+        }
+    }
+
+    /// Use the given DNS servers instead of the ones provided by DHCP or the static IP configuration.
+    ///
+    /// They are used by `Network::resolve` and reported by `get_status` until `clear_dns_servers` is called.
+    pub fn set_dns_servers(&mut self, primary: Ipv4Addr, secondary: Option<Ipv4Addr>) {
+        self.dns_servers = Some([
+            Some(Ipv4Address(primary.octets())),
+            secondary.map(|secondary| Ipv4Address(secondary.octets())),
+        ]);
+    }
+
+    /// Go back to using the DNS servers provided by DHCP or the static IP configuration.
+    pub fn clear_dns_servers(&mut self) {
+        self.dns_servers = None;
+    }
+
+    /// Start connecting using the configuration set via `set_configuration`.
+    ///
+    /// Progress can be observed via `get_status`.
+    pub fn connect(&mut self) -> Result<(), WifiError> {
+        critical_section::with(|cs| {
+            crate::wifi::LAST_DISCONNECT_REASON
+                .borrow_ref_mut(cs)
+                .take()
+        });
+        self.user_disconnected = false;
+        self.connect_started_at = Some(current_millis());
+        self.got_ip_at = None;
+        self.connect_retries_left = self.connect_retries;
+
+        let res = crate::wifi::wifi_connect();
+        if res != 0 {
+            return Err(WifiError::from_raw(res));
+        }
+
+        Ok(())
+    }
+
+    /// Disconnect from the AP.
+    ///
+    /// The IPv4 configuration obtained via DHCP is dropped, a static configuration is kept.
+    /// Call `connect` to connect again using the current configuration.
+    pub fn disconnect(&mut self) -> Result<(), WifiError> {
+        self.user_disconnected = true;
+
+        let res = crate::wifi::wifi_disconnect();
+        if res != 0 {
+            return Err(WifiError::from_raw(res));
+        }
+
+        if !self.static_ip {
+            self.reset_ipv4_config();
+        }
+
+        Ok(())
+    }
+
+    /// Check if the station is associated with an AP, regardless of having an IP address.
+    ///
+    /// Together with `dhcp_state` this tells apart being connected but still waiting for an
+    /// address via DHCP.
+    pub fn is_link_up(&self) -> bool {
+        matches!(
+            crate::wifi::get_wifi_state(),
+            crate::wifi::WifiState::StaConnected
+        )
+    }
+
+    /// Get the signal strength of the AP the station is connected to in dBm.
+    pub fn current_rssi(&self) -> Result<i8, WifiError> {
+        Ok(self.connected_ap_record()?.rssi)
+    }
+
+    /// Get a `WifiEvent::StaBssRssiLow` once the signal of the AP drops below `dbm`.
+    ///
+    /// The driver disarms the threshold when the event fires, call this again after handling
+    /// the event to get notified the next time. The threshold has to be between -100 and 0 dBm.
+    pub fn set_rssi_threshold(&mut self, dbm: i8) -> Result<(), WifiError> {
+        if !(-100..=0).contains(&dbm) {
+            return Err(WifiError::InvalidArgument);
+        }
+
+        let res = unsafe { crate::binary::include::esp_wifi_set_rssi_threshold(dbm as i32) };
+        if res != 0 {
+            return Err(WifiError::from_raw(res));
+        }
+
+        Ok(())
+    }
+
+    /// Get information about the AP the station is connected to.
+    ///
+    /// The information is decoded the same way as the results of `scan_n`.
+    pub fn connected_ap_info(&self) -> Result<AccessPointInfo, WifiError> {
+        Ok(access_point_info_from_raw(&self.connected_ap_record()?))
+    }
+
+    fn connected_ap_record(&self) -> Result<crate::binary::include::wifi_ap_record_t, WifiError> {
+        if !matches!(
+            crate::wifi::get_wifi_state(),
+            crate::wifi::WifiState::StaConnected
+        ) {
+            return Err(WifiError::NotConnected);
+        }
+
+        let mut record: crate::binary::include::wifi_ap_record_t = unsafe { core::mem::zeroed() };
+        let res = unsafe { crate::binary::include::esp_wifi_sta_get_ap_info(&mut record) };
+        if res != 0 {
+            return Err(WifiError::from_raw(res));
+        }
+
+        Ok(record)
+    }
+
+    /// Configure the station to use the AP with the given BSSID and start connecting.
+    ///
+    /// This pins the connection to one AP even if other APs share the same SSID.
+    pub fn connect_bssid(
+        &mut self,
+        ssid: &str,
+        password: &str,
+        bssid: [u8; 6],
+    ) -> Result<(), WifiError> {
+        if ssid.len() > 32 || password.len() > 64 {
+            return Err(WifiError::InvalidArgument);
+        }
+
+        embedded_svc::wifi::Wifi::set_configuration(
+            self,
+            &embedded_svc::wifi::Configuration::Client(embedded_svc::wifi::ClientConfiguration {
+                ssid: ssid.into(),
+                bssid: Some(bssid),
+                password: password.into(),
+                ..Default::default()
+            }),
+        )?;
+
+        self.connect()
+    }
+
+    /// Refuse to connect to APs with a weaker authentication method than `auth`.
+    ///
+    /// This protects against joining an open or WEP network using the same SSID as the
+    /// intended one. `AuthMethod::None` (the default) accepts any AP.
+    pub fn set_min_auth(&mut self, auth: AuthMethod) -> Result<(), WifiError> {
+        let res = crate::wifi::wifi_set_sta_min_auth(auth_method_to_raw(auth));
+        if res != 0 {
+            return Err(WifiError::from_raw(res));
         }
 
-        Ok(())
+        Ok(())
+    }
+
+    /// Set how many beacon intervals the station sleeps between waking up for a beacon
+    /// while `PowerSaveMode::Maximum` is used, 3 by default.
+    ///
+    /// The interval is announced to the AP when connecting, so it only takes effect with the
+    /// next connect. The AP has to honor it and buffer the traffic for the station that long,
+    /// the latency of incoming traffic grows accordingly.
+    pub fn set_listen_interval(&mut self, intervals: u16) -> Result<(), WifiError> {
+        if intervals == 0 {
+            return Err(WifiError::InvalidArgument);
+        }
+
+        let res = crate::wifi::wifi_set_sta_listen_interval(intervals);
+        if res != 0 {
+            return Err(WifiError::from_raw(res));
+        }
+
+        Ok(())
+    }
+
+    /// Connect to a WPA2-Enterprise (802.1X) network using PEAP or TTLS.
+    ///
+    /// Setting a configuration via `set_configuration` switches back to pre-shared keys.
+    pub fn connect_enterprise(&mut self, config: EnterpriseConfig) -> Result<(), WifiError> {
+        if config.ssid.len() > 32 {
+            return Err(WifiError::InvalidArgument);
+        }
+
+        embedded_svc::wifi::Wifi::set_configuration(
+            self,
+            &embedded_svc::wifi::Configuration::Client(embedded_svc::wifi::ClientConfiguration {
+                ssid: config.ssid.into(),
+                auth_method: AuthMethod::WPA2Enterprise,
+                ..Default::default()
+            }),
+        )?;
+
+        let res = crate::wifi::wifi_enterprise_enable(&config);
+        if res != 0 {
+            crate::wifi::wifi_enterprise_disable();
+            return Err(WifiError::from_raw(res));
+        }
+
+        self.connect()
+    }
+
+    /// Scan for APs with `ssid` and check if any of them requires a password.
+    ///
+    /// Returns `false` if no AP with the SSID was found.
+    pub fn requires_password(&mut self, ssid: &str) -> Result<bool, WifiError> {
+        let (records, _) = self.scan_with_config::<4>(ScanConfig {
+            ssid: Some(ssid),
+            ..Default::default()
+        })?;
+
+        Ok(records
+            .iter()
+            .any(|record| record.auth_method != AuthMethod::None))
+    }
+
+    /// Set `conf`, connect and wait until the station is connected to the AP.
+    ///
+    /// Gives up with `WifiError::ConnectTimeout` after `timeout_ms`. If the AP rejects the
+    /// credentials `WifiError::ConnectFailed` is returned right away instead of retrying until
+    /// the timeout, or `WifiError::PasswordRequired` if no password was given. The latter is
+    /// also returned if an open configuration isn't able to find the AP but a scan still finds
+    /// a secured one with the SSID, see `requires_password`. With
+    /// `set_connect_retries` failed attempts are retried first and once they are used up the
+    /// reason of the last failure is returned. This doesn't wait for an IP address, keep calling
+    /// `poll_dhcp` for that.
+    pub fn connect_blocking(
+        &mut self,
+        conf: &embedded_svc::wifi::Configuration,
+        timeout_ms: u64,
+    ) -> Result<(), WifiError> {
+        embedded_svc::wifi::Wifi::set_configuration(self, conf)?;
+        self.connect()?;
+
+        let open_ssid = match conf {
+            embedded_svc::wifi::Configuration::Client(conf)
+            | embedded_svc::wifi::Configuration::Mixed(conf, _)
+                if conf.auth_method != AuthMethod::WPA2Enterprise
+                    && client_password(conf).is_empty() =>
+            {
+                Some(conf.ssid.as_str())
+            }
+            _ => None,
+        };
+
+        let deadline = current_millis() + timeout_ms;
+        loop {
+            if matches!(
+                crate::wifi::get_wifi_state(),
+                crate::wifi::WifiState::StaConnected
+            ) {
+                return Ok(());
+            }
+
+            let failure = if self.connect_retries_left.is_some() {
+                self.check_connect_retry()
+            } else {
+                self.last_disconnect_reason().filter(|reason| {
+                    is_auth_failure(*reason)
+                        || (open_ssid.is_some() && *reason == DisconnectReason::NoApFound)
+                })
+            };
+
+            if let Some(reason) = failure {
+                let password_required = match open_ssid {
+                    Some(_) if is_auth_failure(reason) => true,
+                    Some(ssid) if reason == DisconnectReason::NoApFound => {
+                        self.requires_password(ssid).unwrap_or(false)
+                    }
+                    _ => false,
+                };
+
+                return Err(if password_required {
+                    WifiError::PasswordRequired
+                } else {
+                    WifiError::ConnectFailed(reason)
+                });
+            }
+
+            if current_millis() >= deadline {
+                return Err(WifiError::ConnectTimeout);
+            }
+
+            crate::wait_for_interrupt();
+        }
+    }
+
+    /// Get how long the last connect, started by `connect`, took to get connected
+    /// to the AP and to get an IP address via DHCP.
+    pub fn last_connect_timing(&self) -> ConnectTiming {
+        let started_at = match self.connect_started_at {
+            Some(started_at) => started_at,
+            None => return ConnectTiming::default(),
+        };
+
+        let connected_at =
+            critical_section::with(|cs| *crate::wifi::STA_CONNECTED_AT.borrow_ref(cs))
+                .filter(|connected_at| *connected_at >= started_at);
+
+        ConnectTiming {
+            connected: connected_at.map(|at| Duration::from_millis(at - started_at)),
+            got_ip: self
+                .got_ip_at
+                .map(|at| Duration::from_millis(at - started_at)),
+        }
+    }
+
+    /// Dump the driver's RX/TX statistics, including retransmissions, to the driver log.
+    ///
+    /// The driver doesn't offer a way to read these counters programmatically.
+    /// The output is only visible with the `wifi_logs` feature enabled.
+    pub fn dump_rxtx_statistics(&self) -> Result<(), WifiError> {
+        let res = unsafe {
+            crate::binary::include::esp_wifi_statis_dump(crate::binary::include::WIFI_STATIS_RXTX)
+        };
+        if res != 0 {
+            return Err(WifiError::from_raw(res));
+        }
+
+        Ok(())
+    }
+
+    /// Get the country information currently in effect.
+    ///
+    /// When running as a soft-AP this is the country IE advertised in the beacons,
+    /// use `Country::is_advertised` to check if there is one at all.
+    pub fn country(&self) -> Result<Country, WifiError> {
+        crate::wifi::get_country().map_err(WifiError::from_raw)
+    }
+
+    /// Set the power save mode of the modem.
+    ///
+    /// With `PowerSaveMode::Minimum` the station wakes up to receive every DTIM beacon, with
+    /// `PowerSaveMode::Maximum` it only wakes up every listen interval, set via
+    /// `set_listen_interval` (3 beacon intervals by default).
+    /// The longer the radio sleeps, the less current is drawn but frames buffered by the AP are
+    /// received with more latency. The mode is kept by the driver across reconnects.
+    pub fn set_power_save(&mut self, mode: PowerSaveMode) -> Result<(), WifiError> {
+        let res = crate::wifi::set_power_save_mode(mode);
+        if res != 0 {
+            return Err(WifiError::from_raw(res));
+        }
+
+        Ok(())
+    }
+
+    /// Set the primary and secondary channel of the radio.
+    ///
+    /// In soft-AP mode this is the channel the AP is running on. When sniffing it is the channel
+    /// frames are captured on. While the station is connected the channel follows the AP.
+    pub fn set_channel(
+        &mut self,
+        primary: u8,
+        secondary: SecondaryChannel,
+    ) -> Result<(), WifiError> {
+        if !(1..=14).contains(&primary) {
+            return Err(WifiError::InvalidArgument);
+        }
+
+        let second = match secondary {
+            SecondaryChannel::None => {
+                crate::binary::include::wifi_second_chan_t_WIFI_SECOND_CHAN_NONE
+            }
+            SecondaryChannel::Above => {
+                crate::binary::include::wifi_second_chan_t_WIFI_SECOND_CHAN_ABOVE
+            }
+            SecondaryChannel::Below => {
+                crate::binary::include::wifi_second_chan_t_WIFI_SECOND_CHAN_BELOW
+            }
+        };
+
+        let res = unsafe { crate::binary::include::esp_wifi_set_channel(primary, second) };
+        if res != 0 {
+            return Err(WifiError::from_raw(res));
+        }
+
+        Ok(())
+    }
+
+    /// Set the regulatory domain.
+    ///
+    /// `cc` has to be a two letter country code. The channels from `start_channel` on are allowed,
+    /// `num_channels` in total. With `CountryPolicy::Auto` the country information of the AP the
+    /// station is connected to is used instead while connected.
+    pub fn set_country(
+        &mut self,
+        cc: &str,
+        start_channel: u8,
+        num_channels: u8,
+        policy: CountryPolicy,
+    ) -> Result<(), WifiError> {
+        let code = cc.as_bytes();
+        if code.len() != 2 || !code.iter().all(|c| c.is_ascii_alphabetic()) {
+            return Err(WifiError::InvalidArgument);
+        }
+
+        let res = crate::wifi::set_country(
+            [code[0].to_ascii_uppercase(), code[1].to_ascii_uppercase()],
+            start_channel,
+            num_channels,
+            policy,
+        );
+        if res != 0 {
+            return Err(WifiError::from_raw(res));
+        }
+
+        Ok(())
+    }
+
+    /// Set the 802.11 protocols the given interface uses, the default is 802.11b/g/n.
+    ///
+    /// `P802D11BG` and `P802D11BGN` include the protocols before them, e.g. use only
+    /// `P802D11B` for the best range. `P802D11LR` is Espressif's long range mode which only
+    /// works with other Espressif devices.
+    pub fn set_protocol(
+        &mut self,
+        interface: WifiInterface,
+        protocols: EnumSet<Protocol>,
+    ) -> Result<(), WifiError> {
+        if protocols.is_empty() {
+            return Err(WifiError::InvalidArgument);
+        }
+
+        let res = crate::wifi::wifi_set_protocol(interface, protocols_to_raw(protocols));
+        if res != 0 {
+            return Err(WifiError::from_raw(res));
+        }
+
+        Ok(())
+    }
+
+    /// Set the channel bandwidth of the given interface.
+    ///
+    /// 40 MHz needs 802.11n enabled via `set_protocol`, the driver rejects it otherwise.
+    /// Whether 40 MHz are actually used also depends on the AP and the secondary channel.
+    pub fn set_bandwidth(
+        &mut self,
+        interface: WifiInterface,
+        bandwidth: Bandwidth,
+    ) -> Result<(), WifiError> {
+        let res = crate::wifi::wifi_set_bandwidth(interface, bandwidth);
+        if res != 0 {
+            return Err(WifiError::from_raw(res));
+        }
+
+        Ok(())
+    }
+
+    /// Set whether Wi-Fi or Bluetooth LE gets precedence while both are active.
+    ///
+    /// This only exists if both the `wifi` and `ble` features are enabled. Call it after both
+    /// Wi-Fi and BLE are initialized, initializing BLE (re-)initializes the coexistence
+    /// module and any preference set before is lost.
+    #[cfg(coex)]
+    pub fn set_coex_mode(&mut self, mode: crate::wifi::CoexMode) -> Result<(), WifiError> {
+        let res = crate::wifi::coex_set_mode(mode);
+        if res != 0 {
+            return Err(WifiError::from_raw(res));
+        }
+
+        Ok(())
+    }
+
+    /// Limit the transmit power, in units of 0.25 dBm.
+    ///
+    /// The driver accepts values from 8 (2 dBm) to 84 (21 dBm).
+    pub fn set_max_tx_power(&mut self, power_quarter_dbm: i8) -> Result<(), WifiError> {
+        if !(8..=84).contains(&power_quarter_dbm) {
+            return Err(WifiError::InvalidArgument);
+        }
+
+        let res = unsafe { crate::binary::include::esp_wifi_set_max_tx_power(power_quarter_dbm) };
+        if res != 0 {
+            return Err(WifiError::from_raw(res));
+        }
+
+        Ok(())
+    }
+
+    /// Get the transmit power limit, in units of 0.25 dBm.
+    pub fn max_tx_power(&self) -> Result<i8, WifiError> {
+        let mut power = 0;
+        let res = unsafe { crate::binary::include::esp_wifi_get_max_tx_power(&mut power) };
+        if res != 0 {
+            return Err(WifiError::from_raw(res));
+        }
+
+        Ok(power)
+    }
+
+    /// Get the MAC address of the given interface.
+    pub fn mac_address(&self, interface: WifiInterface) -> Result<[u8; 6], WifiError> {
+        let mut mac = [0u8; 6];
+        let res = crate::wifi::wifi_get_mac(interface, &mut mac);
+        if res != 0 {
+            return Err(WifiError::from_raw(res));
+        }
+
+        Ok(mac)
+    }
+
+    /// Override the MAC address of the given interface.
+    ///
+    /// The address must be a unicast address (bit 0 of the first byte cleared) and the station
+    /// and soft-AP addresses must differ. The driver might refuse to change the address of an active
+    /// interface. If the network stack is bound to the interface it uses the new address, too.
+    pub fn set_mac_address(
+        &mut self,
+        interface: WifiInterface,
+        mac: [u8; 6],
+    ) -> Result<(), WifiError> {
+        if mac[0] & 0x01 != 0 {
+            return Err(WifiError::InvalidArgument);
+        }
+
+        let res = crate::wifi::wifi_set_mac(interface, &mac);
+        if res != 0 {
+            return Err(WifiError::from_raw(res));
+        }
+
+        if crate::wifi::is_ap_data_interface() == (interface == WifiInterface::Ap) {
+            self.network_interface
+                .set_hardware_addr(HardwareAddress::Ethernet(EthernetAddress(mac)));
+        }
+
+        Ok(())
+    }
+
+    /// Capture raw 802.11 frames.
+    ///
+    /// `cb` gets the frame (including the FCS), its kind and the RSSI in dBm. It's called from the
+    /// WiFi driver's task so it should return quickly.
+    /// Sniffing doesn't work together with being connected to an AP, use `set_channel` to select the
+    /// channel to capture on.
+    pub fn start_sniffer(
+        &mut self,
+        filter: SnifferFilter,
+        cb: fn(&[u8], FrameKind, i8),
+    ) -> Result<(), WifiError> {
+        let res = crate::wifi::sniffer_start(filter, cb);
+        if res != 0 {
+            return Err(WifiError::from_raw(res));
+        }
+
+        Ok(())
+    }
+
+    /// Capture the channel state information (CSI) of received packets.
+    ///
+    /// `cb` gets the raw CSI together with the sender, RSSI and channel of every packet.
+    /// It's called from the WiFi driver's task so it should return quickly.
+    pub fn enable_csi(&mut self, config: CsiConfig, cb: fn(&CsiData)) -> Result<(), WifiError> {
+        let res = crate::wifi::csi_enable(config, cb);
+        if res != 0 {
+            crate::wifi::csi_disable();
+            return Err(WifiError::from_raw(res));
+        }
+
+        Ok(())
+    }
+
+    /// Stop capturing CSI, the callback isn't called anymore afterwards.
+    pub fn disable_csi(&mut self) -> Result<(), WifiError> {
+        let res = crate::wifi::csi_disable();
+        if res != 0 {
+            return Err(WifiError::from_raw(res));
+        }
+
+        Ok(())
+    }
+
+    /// Stop the radio, it can be started again via `start`.
+    ///
+    /// All the TCP connections are aborted, UDP sockets are closed and the IPv4 configuration
+    /// obtained via DHCP is dropped.
+    pub fn stop(&mut self) -> Result<(), WifiError> {
+        self.release_sockets();
+        if !self.static_ip {
+            self.reset_ipv4_config();
+        }
+        self.user_disconnected = true;
+
+        let res = crate::wifi::wifi_stop();
+        if res != 0 {
+            return Err(WifiError::from_raw(res));
+        }
+
+        unsafe {
+            crate::wifi::WIFI_STATE =
+                crate::binary::include::wifi_event_t_WIFI_EVENT_STA_STOP as i32;
+            crate::wifi::AP_STATE = crate::binary::include::wifi_event_t_WIFI_EVENT_AP_STOP as i32;
+        }
+
+        Ok(())
+    }
+
+    /// Start the radio again after it was stopped via `stop`.
+    ///
+    /// The configuration, the power save mode and the country are kept, call `connect` to
+    /// connect again.
+    pub fn start(&mut self) -> Result<(), WifiError> {
+        let res = crate::wifi::wifi_start();
+        if res != 0 {
+            return Err(WifiError::from_raw(res));
+        }
+
+        Ok(())
+    }
+
+    /// Stop the radio and free all the memory the driver uses.
+    ///
+    /// Use `init` to bring the driver back, the configuration has to be set again afterwards.
+    pub fn deinit(&mut self) -> Result<(), WifiError> {
+        self.stop()?;
+
+        let res = crate::wifi::wifi_deinit();
+        if res != 0 {
+            return Err(WifiError::from_raw(res));
+        }
+
+        Ok(())
+    }
+
+    /// Initialize and start the driver again after `deinit`.
+    pub fn init(&mut self) -> Result<(), WifiError> {
+        let res = crate::wifi::wifi_init();
+        if res != 0 {
+            return Err(WifiError::from_raw(res));
+        }
+
+        self.start()
+    }
+
+    /// Abort all TCP connections and close all UDP sockets.
+    fn release_sockets(&mut self) {
+        for (_, socket) in self.network_interface.sockets_mut() {
+            match socket {
+                smoltcp::socket::Socket::Tcp(tcp) => tcp.abort(),
+                smoltcp::socket::Socket::Udp(udp) => udp.close(),
+                _ => (),
+            }
+        }
+    }
+
+    /// Stop capturing frames and go back to normal operation.
+    pub fn stop_sniffer(&mut self) -> Result<(), WifiError> {
+        let res = crate::wifi::sniffer_stop();
+        if res != 0 {
+            return Err(WifiError::from_raw(res));
+        }
+
+        Ok(())
+    }
+
+    /// Get the reason why the station got disconnected or failed to connect the last time.
+    ///
+    /// Returns `None` if there was no disconnect since the last call to `connect`.
+    /// The driver doesn't consider an AP requiring a password when connecting with an open
+    /// configuration, that ends with `DisconnectReason::NoApFound`. Use `requires_password`
+    /// to tell this apart from an AP which is out of range.
+    pub fn last_disconnect_reason(&self) -> Option<DisconnectReason> {
+        critical_section::with(|cs| *crate::wifi::LAST_DISCONNECT_REASON.borrow_ref(cs))
+    }
+
+    /// Register a function which is called whenever the Wi-Fi state changes, `None` removes it.
+    ///
+    /// `WifiEvent::GotIp` is reported from `poll_dhcp`, all the other events are reported
+    /// from the Wi-Fi task right when they happen so the callback should return quickly.
+    pub fn on_event(&mut self, cb: Option<fn(WifiEvent)>) {
+        crate::wifi::set_event_callback(cb);
+    }
+
+    /// Limit the size of the IP packets sent, e.g. to avoid fragmentation behind a VPN gateway.
+    ///
+    /// The MSS advertised in the SYNs of new TCP connections is clamped accordingly to
+    /// `mtu` - 40, connections which are already open keep the MSS they advertised.
+    /// The MTU has to be between 576 and 1500.
+    pub fn set_mtu(&mut self, mtu: u16) -> Result<(), WifiError> {
+        if !(MIN_MTU..=crate::wifi::DEFAULT_MTU).contains(&(mtu as usize)) {
+            return Err(WifiError::InvalidArgument);
+        }
+
+        self.network_interface.device_mut().set_mtu(mtu as usize);
+        Ok(())
+    }
+
+    /// Get the MTU set via `set_mtu`, 1500 by default.
+    pub fn mtu(&self) -> u16 {
+        self.network_interface.device().mtu() as u16
+    }
+
+    /// Get the MSS advertised in the SYNs of new TCP connections, it follows the MTU.
+    pub fn tcp_mss(&self) -> u16 {
+        self.mtu() - TCP_IP_HEADER_LEN
+    }
+
+    /// Get the number of frames and bytes received and sent by the network interface.
+    pub fn device_stats(&self) -> DeviceStats {
+        self.network_interface.device().stats()
+    }
+
+    /// Use `rx_frames` and `tx_frames` as the receive and transmit queues of the device.
+    ///
+    /// See `WifiDevice::set_queues`, call it before connecting.
+    pub fn set_frame_queues(
+        &mut self,
+        rx_frames: &'static mut [DataFrame],
+        tx_frames: &'static mut [DataFrame],
+    ) {
+        self.network_interface
+            .device_mut()
+            .set_queues(rx_frames, tx_frames);
+    }
+
+    /// Join the IPv4 multicast group `addr` and announce it via IGMP.
+    ///
+    /// The interface needs an IPv4 address first (via DHCP or `set_static_ip`), the IGMP
+    /// report is sent from it. Otherwise `WifiError::NotConnected` is returned.
+    /// At most four groups can be joined at the same time.
+    pub fn join_multicast_group(&mut self, addr: Ipv4Address) -> Result<(), WifiError> {
+        self.check_multicast_group(addr)?;
+
+        self.network_interface
+            .join_multicast_group(addr, timestamp())?;
+        // make sure the report leaves right away, errors of unrelated ingress packets don't matter
+        self.network_interface.poll(timestamp()).ok();
+
+        Ok(())
+    }
+
+    /// Leave the IPv4 multicast group `addr` joined via `join_multicast_group`.
+    ///
+    /// Like joining this needs an IPv4 address to send the IGMP leave message from.
+    pub fn leave_multicast_group(&mut self, addr: Ipv4Address) -> Result<(), WifiError> {
+        self.check_multicast_group(addr)?;
+
+        self.network_interface
+            .leave_multicast_group(addr, timestamp())?;
+        self.network_interface.poll(timestamp()).ok();
+
+        Ok(())
+    }
+
+    /// Forget all the MAC addresses learned via ARP.
+    ///
+    /// Useful when a host, e.g. the gateway after a failover, changed its MAC address. Until
+    /// the stale entry expires by itself, which takes a minute, packets would be sent to the
+    /// old one. The addresses are looked up again on demand.
+    ///
+    /// smoltcp doesn't give access to the entries of the cache, so they can't be inspected and
+    /// static entries aren't supported.
+    pub fn flush_neighbor_cache(&mut self) {
+        // smoltcp flushes the cache whenever the IP addresses are updated
+        self.network_interface.update_ip_addrs(|_| ());
+    }
+
+    fn check_multicast_group(&self, addr: Ipv4Address) -> Result<(), WifiError> {
+        if !addr.is_multicast() {
+            return Err(WifiError::InvalidArgument);
+        }
+
+        let has_address = self.network_interface.ip_addrs().iter().any(
+            |cidr| matches!(cidr.address(), IpAddress::Ipv4(address) if !address.is_unspecified()),
+        );
+        if !has_address {
+            return Err(WifiError::NotConnected);
+        }
+
+        Ok(())
+    }
+
+    /// Get the power save mode currently in effect in the driver.
+    pub fn power_save_mode(&self) -> Result<PowerSaveMode, WifiError> {
+        crate::wifi::get_power_save_mode().map_err(WifiError::from_raw)
+    }
+}
+
+fn access_point_info_from_raw(
+    record: &crate::binary::include::wifi_ap_record_t,
+) -> AccessPointInfo {
+    AccessPointInfo {
+        ssid: str_from_raw(&record.ssid),
+        bssid: record.bssid,
+        channel: record.primary,
+        secondary_channel: secondary_channel_from_raw(record.second),
+        signal_strength: record.rssi as u8,
+        protocols: protocols_from_raw(record),
+        auth_method: auth_method_from_raw(record.authmode),
+    }
+}
+
+fn auth_method_from_raw(authmode: crate::binary::include::wifi_auth_mode_t) -> AuthMethod {
+    match authmode {
+        crate::binary::include::wifi_auth_mode_t_WIFI_AUTH_OPEN => AuthMethod::None,
+        crate::binary::include::wifi_auth_mode_t_WIFI_AUTH_WEP => AuthMethod::WEP,
+        crate::binary::include::wifi_auth_mode_t_WIFI_AUTH_WPA_PSK => AuthMethod::WPA,
+        crate::binary::include::wifi_auth_mode_t_WIFI_AUTH_WPA2_PSK => AuthMethod::WPA2Personal,
+        crate::binary::include::wifi_auth_mode_t_WIFI_AUTH_WPA_WPA2_PSK => {
+            AuthMethod::WPAWPA2Personal
+        }
+        crate::binary::include::wifi_auth_mode_t_WIFI_AUTH_WPA2_ENTERPRISE => {
+            AuthMethod::WPA2Enterprise
+        }
+        crate::binary::include::wifi_auth_mode_t_WIFI_AUTH_WPA3_PSK => AuthMethod::WPA3Personal,
+        crate::binary::include::wifi_auth_mode_t_WIFI_AUTH_WPA2_WPA3_PSK => {
+            AuthMethod::WPA2WPA3Personal
+        }
+        crate::binary::include::wifi_auth_mode_t_WIFI_AUTH_WAPI_PSK => AuthMethod::WAPIPersonal,
+        // don't report an AP using e.g. OWE or WPA3-Enterprise as open
+        _ => {
+            log::warn!("unknown auth mode {}, reporting it as WPA2", authmode);
+            AuthMethod::WPA2Personal
+        }
+    }
+}
+
+fn protocols_to_raw(protocols: EnumSet<Protocol>) -> u8 {
+    use crate::binary::include::{
+        WIFI_PROTOCOL_11B, WIFI_PROTOCOL_11G, WIFI_PROTOCOL_11N, WIFI_PROTOCOL_LR,
+    };
+
+    let mut raw = 0;
+    for protocol in protocols {
+        raw |= match protocol {
+            Protocol::P802D11B => WIFI_PROTOCOL_11B,
+            Protocol::P802D11BG => WIFI_PROTOCOL_11B | WIFI_PROTOCOL_11G,
+            Protocol::P802D11BGN => WIFI_PROTOCOL_11B | WIFI_PROTOCOL_11G | WIFI_PROTOCOL_11N,
+            Protocol::P802D11LR => WIFI_PROTOCOL_LR,
+        };
+    }
+    raw as u8
+}
+
+fn protocols_from_raw(record: &crate::binary::include::wifi_ap_record_t) -> EnumSet<Protocol> {
+    let mut protocols = EnumSet::empty();
+    if record.phy_11b() != 0 {
+        protocols.insert(Protocol::P802D11B);
+    }
+    if record.phy_11g() != 0 {
+        protocols.insert(Protocol::P802D11BG);
+    }
+    if record.phy_11n() != 0 {
+        protocols.insert(Protocol::P802D11BGN);
+    }
+    if record.phy_lr() != 0 {
+        protocols.insert(Protocol::P802D11LR);
+    }
+    protocols
+}
+
+fn secondary_channel_from_raw(
+    second: crate::binary::include::wifi_second_chan_t,
+) -> SecondaryChannel {
+    match second {
+        crate::binary::include::wifi_second_chan_t_WIFI_SECOND_CHAN_NONE => SecondaryChannel::None,
+        crate::binary::include::wifi_second_chan_t_WIFI_SECOND_CHAN_ABOVE => {
+            SecondaryChannel::Above
+        }
+        crate::binary::include::wifi_second_chan_t_WIFI_SECOND_CHAN_BELOW => {
+            SecondaryChannel::Below
+        }
+        _ => {
+            log::warn!("unknown secondary channel {}", second);
+            SecondaryChannel::None
+        }
+    }
+}
+
+/// Check if the AP rejected the credentials, usually a wrong password.
+fn is_auth_failure(reason: DisconnectReason) -> bool {
+    matches!(
+        reason,
+        DisconnectReason::AuthFail
+            | DisconnectReason::FourWayHandshakeTimeout
+            | DisconnectReason::HandshakeTimeout
+    )
+}
+
+/// The password to configure, empty for an open network.
+///
+/// Besides an empty password `AuthMethod::None` selects an open network, a password given
+/// anyway is ignored then.
+fn client_password(conf: &embedded_svc::wifi::ClientConfiguration) -> &str {
+    if conf.auth_method == AuthMethod::None {
+        ""
+    } else {
+        &conf.password
+    }
+}
+
+fn auth_method_to_raw(auth_method: AuthMethod) -> crate::binary::include::wifi_auth_mode_t {
+    match auth_method {
+        AuthMethod::None => crate::binary::include::wifi_auth_mode_t_WIFI_AUTH_OPEN,
+        AuthMethod::WEP => crate::binary::include::wifi_auth_mode_t_WIFI_AUTH_WEP,
+        AuthMethod::WPA => crate::binary::include::wifi_auth_mode_t_WIFI_AUTH_WPA_PSK,
+        AuthMethod::WPA2Personal => crate::binary::include::wifi_auth_mode_t_WIFI_AUTH_WPA2_PSK,
+        AuthMethod::WPAWPA2Personal => {
+            crate::binary::include::wifi_auth_mode_t_WIFI_AUTH_WPA_WPA2_PSK
+        }
+        AuthMethod::WPA2Enterprise => {
+            crate::binary::include::wifi_auth_mode_t_WIFI_AUTH_WPA2_ENTERPRISE
+        }
+        AuthMethod::WPA3Personal => crate::binary::include::wifi_auth_mode_t_WIFI_AUTH_WPA3_PSK,
+        AuthMethod::WPA2WPA3Personal => {
+            crate::binary::include::wifi_auth_mode_t_WIFI_AUTH_WPA2_WPA3_PSK
+        }
+        AuthMethod::WAPIPersonal => crate::binary::include::wifi_auth_mode_t_WIFI_AUTH_WAPI_PSK,
+    }
+}
+
+/// Time elapsed from calling `Wifi::connect` until reaching a connection state.
+///
+/// A field is `None` if the state wasn't reached (yet).
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ConnectTiming {
+    /// Time until the station got connected to the AP
+    pub connected: Option<Duration>,
+    /// Time until an IP address was assigned via DHCP
+    pub got_ip: Option<Duration>,
+}
+
+/// Channel and bandwidth information of the station.
+#[derive(Debug, Copy, Clone)]
+pub struct ChannelInfo {
+    /// Primary channel
+    pub primary: u8,
+    /// Position of the secondary channel (only relevant for 40 MHz bandwidth)
+    pub secondary: SecondaryChannel,
+    /// Channel bandwidth
+    pub bandwidth: Bandwidth,
+    /// Center frequency of the occupied spectrum in MHz
+    pub center_frequency: u16,
+}
+
+/// Convert a NUL terminated string of the driver (e.g. an SSID) into a string.
+///
+/// The string ends at the first NUL byte and is at most `N` bytes long. SSIDs and passwords are
+/// arbitrary bytes - if it isn't valid UTF-8 only the valid prefix is kept and a warning is logged.
+fn str_from_raw<const N: usize>(raw: &[u8]) -> heapless::String<N> {
+    let raw = &raw[..raw.len().min(N)];
+    let len = raw.iter().position(|&b| b == 0).unwrap_or(raw.len());
+    let bytes = &raw[..len];
+
+    let valid = match core::str::from_utf8(bytes) {
+        Ok(valid) => valid,
+        Err(err) => {
+            log::warn!("{:x?} is not valid UTF-8, truncating it", bytes);
+            core::str::from_utf8(&bytes[..err.valid_up_to()]).unwrap_or("")
+        }
+    };
+
+    let mut string = heapless::String::new();
+    // can't fail, `valid` is at most N bytes long
+    string.push_str(valid).ok();
+    string
+}
+
+/// Get the center frequency in MHz of a 2.4 GHz channel.
+fn channel_to_frequency(channel: u8) -> u16 {
+    if channel == 14 {
+        2484
+    } else {
+        2407 + 5 * channel as u16
+    }
+}
+
+/// Errors of the driver and the network stack
+///
+/// The common `esp_err_t` codes returned by the driver are mapped to their own variants,
+/// `ESP_ERR_NO_MEM` and `ESP_ERR_INVALID_ARG` to `OutOfMemory` and `InvalidArgument`.
+/// Any other code is returned as `Unknown`.
+#[derive(Debug, Copy, Clone)]
+pub enum WifiError {
+    /// Any other `esp_err_t` returned by the driver
+    Unknown(i32),
+    SmolTcpError(smoltcp::Error),
+    /// The network interface is already borrowed
+    InterfaceBusy,
+    /// Resolving a hostname failed
+    Dns(DnsError),
+    /// The station isn't connected to an AP
+    NotConnected,
+    /// An argument is out of the range the driver accepts
+    InvalidArgument,
+    /// There is no free socket
+    NoSocket,
+    /// Getting the time via SNTP failed
+    Sntp(SntpError),
+    /// The mDNS responder failed
+    Mdns(MdnsError),
+    /// There is no space left for another event subscription
+    TooManySubscribers,
+    /// The heap is exhausted
+    OutOfMemory,
+    /// The station didn't get connected in time
+    ConnectTimeout,
+    /// The AP rejected the station, usually because of a wrong password
+    ConnectFailed(DisconnectReason),
+    /// The configuration is for an open network but the AP requires a password
+    PasswordRequired,
+    /// The driver is in the wrong state for the operation (`ESP_ERR_INVALID_STATE`)
+    InvalidState,
+    /// A buffer or value has an invalid size (`ESP_ERR_INVALID_SIZE`)
+    InvalidSize,
+    /// The requested resource wasn't found (`ESP_ERR_NOT_FOUND`)
+    NotFound,
+    /// The operation isn't supported (`ESP_ERR_NOT_SUPPORTED`)
+    NotSupported,
+    /// The driver timed out (`ESP_ERR_TIMEOUT`)
+    Timeout,
+    /// Wi-Fi isn't initialized (`ESP_ERR_WIFI_NOT_INIT`)
+    WifiNotInit,
+    /// Wi-Fi isn't started (`ESP_ERR_WIFI_NOT_STARTED`)
+    WifiNotStarted,
+    /// Wi-Fi isn't stopped (`ESP_ERR_WIFI_NOT_STOPPED`)
+    WifiNotStopped,
+    /// The interface isn't valid (`ESP_ERR_WIFI_IF`)
+    WifiInterface,
+    /// The operation isn't possible in the current mode (`ESP_ERR_WIFI_MODE`)
+    WifiMode,
+    /// The driver is busy, e.g. connecting or scanning (`ESP_ERR_WIFI_STATE`)
+    WifiState,
+    /// The control block of the station or soft-AP is broken (`ESP_ERR_WIFI_CONN`)
+    WifiConnection,
+    /// The SSID is invalid (`ESP_ERR_WIFI_SSID`)
+    WifiSsid,
+    /// The password is invalid (`ESP_ERR_WIFI_PASSWORD`)
+    WifiPassword,
+    /// The driver timed out internally (`ESP_ERR_WIFI_TIMEOUT`)
+    WifiTimeout,
+    /// The station isn't associated with an AP (`ESP_ERR_WIFI_NOT_ASSOC`)
+    WifiNotAssociated,
+    /// Transmitting isn't allowed right now (`ESP_ERR_WIFI_TX_DISALLOW`)
+    WifiTxDisallowed,
+}
+
+impl WifiError {
+    /// Map an `esp_err_t` returned by the driver.
+    #[allow(non_upper_case_globals)]
+    pub(crate) fn from_raw(res: i32) -> WifiError {
+        use crate::binary::include::*;
+
+        match res as u32 {
+            ESP_ERR_NO_MEM => WifiError::OutOfMemory,
+            ESP_ERR_INVALID_ARG => WifiError::InvalidArgument,
+            ESP_ERR_INVALID_STATE => WifiError::InvalidState,
+            ESP_ERR_INVALID_SIZE => WifiError::InvalidSize,
+            ESP_ERR_NOT_FOUND => WifiError::NotFound,
+            ESP_ERR_NOT_SUPPORTED => WifiError::NotSupported,
+            ESP_ERR_TIMEOUT => WifiError::Timeout,
+            ESP_ERR_WIFI_NOT_INIT => WifiError::WifiNotInit,
+            ESP_ERR_WIFI_NOT_STARTED => WifiError::WifiNotStarted,
+            ESP_ERR_WIFI_NOT_STOPPED => WifiError::WifiNotStopped,
+            ESP_ERR_WIFI_IF => WifiError::WifiInterface,
+            ESP_ERR_WIFI_MODE => WifiError::WifiMode,
+            ESP_ERR_WIFI_STATE => WifiError::WifiState,
+            ESP_ERR_WIFI_CONN => WifiError::WifiConnection,
+            ESP_ERR_WIFI_SSID => WifiError::WifiSsid,
+            ESP_ERR_WIFI_PASSWORD => WifiError::WifiPassword,
+            ESP_ERR_WIFI_TIMEOUT => WifiError::WifiTimeout,
+            ESP_ERR_WIFI_NOT_ASSOC => WifiError::WifiNotAssociated,
+            ESP_ERR_WIFI_TX_DISALLOW => WifiError::WifiTxDisallowed,
+            _ => WifiError::Unknown(res),
+        }
+    }
+}
+
+impl From<smoltcp::Error> for WifiError {
+    fn from(error: smoltcp::Error) -> Self {
+        WifiError::SmolTcpError(error)
+    }
+}
+
+impl From<DnsError> for WifiError {
+    fn from(error: DnsError) -> Self {
+        WifiError::Dns(error)
     }
 }
 
-#[derive(Debug, Copy, Clone)]
-pub enum WifiError {
-    Unknown(i32),
-    SmolTcpError(smoltcp::Error),
+impl From<SntpError> for WifiError {
+    fn from(error: SntpError) -> Self {
+        WifiError::Sntp(error)
+    }
 }
 
-impl From<smoltcp::Error> for WifiError {
-    fn from(error: smoltcp::Error) -> Self {
-        WifiError::SmolTcpError(error)
+impl From<MdnsError> for WifiError {
+    fn from(error: MdnsError) -> Self {
+        WifiError::Mdns(error)
     }
 }
 
 impl Display for WifiError {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-        write!(f, "{:?}", self)
+        match self {
+            WifiError::Unknown(res) => write!(f, "driver error {}", res),
+            WifiError::SmolTcpError(error) => write!(f, "network stack error: {}", error),
+            WifiError::InterfaceBusy => write!(f, "network interface is busy"),
+            WifiError::Dns(error) => write!(f, "resolving the hostname failed: {:?}", error),
+            WifiError::NotConnected => write!(f, "not connected"),
+            WifiError::InvalidArgument => write!(f, "invalid argument"),
+            WifiError::NoSocket => write!(f, "no free socket"),
+            WifiError::Sntp(error) => write!(f, "getting the time failed: {:?}", error),
+            WifiError::Mdns(error) => write!(f, "mDNS failed: {:?}", error),
+            WifiError::TooManySubscribers => write!(f, "too many event subscriptions"),
+            WifiError::OutOfMemory => write!(f, "out of memory"),
+            WifiError::ConnectTimeout => write!(f, "connecting timed out"),
+            WifiError::ConnectFailed(reason) => write!(f, "connecting failed: {:?}", reason),
+            WifiError::PasswordRequired => write!(f, "the network requires a password"),
+            WifiError::InvalidState => write!(f, "invalid state"),
+            WifiError::InvalidSize => write!(f, "invalid size"),
+            WifiError::NotFound => write!(f, "not found"),
+            WifiError::NotSupported => write!(f, "not supported"),
+            WifiError::Timeout => write!(f, "timed out"),
+            WifiError::WifiNotInit => write!(f, "Wi-Fi is not initialized"),
+            WifiError::WifiNotStarted => write!(f, "Wi-Fi is not started"),
+            WifiError::WifiNotStopped => write!(f, "Wi-Fi is not stopped"),
+            WifiError::WifiInterface => write!(f, "invalid Wi-Fi interface"),
+            WifiError::WifiMode => write!(f, "wrong Wi-Fi mode"),
+            WifiError::WifiState => write!(f, "Wi-Fi is busy"),
+            WifiError::WifiConnection => write!(f, "Wi-Fi internal error"),
+            WifiError::WifiSsid => write!(f, "invalid SSID"),
+            WifiError::WifiPassword => write!(f, "invalid password"),
+            WifiError::WifiTimeout => write!(f, "Wi-Fi timed out"),
+            WifiError::WifiNotAssociated => write!(f, "not associated with an AP"),
+            WifiError::WifiTxDisallowed => write!(f, "transmitting is not allowed"),
+        }
     }
 }
 
 impl<'a> embedded_svc::wifi::Wifi for Wifi<'a> {
     type Error = WifiError;
 
-    /// This currently only supports the `Client` capability.
+    /// This currently supports the `Client`, `AccessPoint` and `Mixed` capabilities.
     fn get_capabilities(&self) -> Result<EnumSet<embedded_svc::wifi::Capability>, Self::Error> {
-        // for now we only support STA mode
         let mut caps = EnumSet::empty();
         caps.insert(embedded_svc::wifi::Capability::Client);
+        caps.insert(embedded_svc::wifi::Capability::AccessPoint);
+        caps.insert(embedded_svc::wifi::Capability::Mixed);
         Ok(caps)
     }
 
@@ -130,10 +1919,28 @@ impl<'a> embedded_svc::wifi::Wifi for Wifi<'a> {
     /// Please note: To ever get into the state of an assigned IP address you need to make sure
     /// that `poll` is called frequently on the network stack and dhcp socket.
     fn get_status(&self) -> Status {
+        let ap_status = match crate::wifi::get_ap_state() {
+            crate::wifi::ApState::ApStart if !crate::wifi::is_ap_data_interface() => {
+                ApStatus::Started(ApIpStatus::Disabled)
+            }
+            crate::wifi::ApState::ApStart => {
+                let has_ip = self
+                    .network_interface
+                    .ipv4_addr()
+                    .map_or(false, |ip| !ip.is_unspecified());
+                if has_ip {
+                    ApStatus::Started(ApIpStatus::Done)
+                } else {
+                    ApStatus::Started(ApIpStatus::Waiting)
+                }
+            }
+            crate::wifi::ApState::ApStop | crate::wifi::ApState::Invalid => ApStatus::Stopped,
+        };
+
         match crate::wifi::get_wifi_state() {
-            crate::wifi::WifiState::WifiReady => Status(ClientStatus::Stopped, ApStatus::Stopped),
-            crate::wifi::WifiState::StaStart => Status(ClientStatus::Starting, ApStatus::Stopped),
-            crate::wifi::WifiState::StaStop => Status(ClientStatus::Stopped, ApStatus::Stopped),
+            crate::wifi::WifiState::WifiReady => Status(ClientStatus::Stopped, ap_status),
+            crate::wifi::WifiState::StaStart => Status(ClientStatus::Starting, ap_status),
+            crate::wifi::WifiState::StaStop => Status(ClientStatus::Stopped, ap_status),
             crate::wifi::WifiState::StaConnected => {
                 let client_ip_status = if let Some(ip) = self.network_interface.ipv4_addr() {
                     if !ip.is_unspecified() {
@@ -141,25 +1948,27 @@ impl<'a> embedded_svc::wifi::Wifi for Wifi<'a> {
                         ip_bytes.copy_from_slice(ip.as_bytes());
 
                         let mut gw_bytes: [u8; 4] = [0; 4];
-                        let mut dns_bytes: [u8; 4] = [0; 4];
+                        let mut prefix_len = 0;
                         if let Some(config) = self.network_config {
+                            prefix_len = config.address.prefix_len();
+
                             if let Some(router) = config.router {
                                 gw_bytes.copy_from_slice(router.as_bytes());
                             }
-
-                            if let Some(dns_server) = config.dns_servers[0] {
-                                dns_bytes.copy_from_slice(dns_server.as_bytes());
-                            }
                         }
 
+                        let [dns, secondary_dns] = self
+                            .dns_servers()
+                            .map(|dns_server| dns_server.map(|addr| Ipv4Addr::from(addr.0)));
+
                         ClientIpStatus::Done(ClientSettings {
                             ip: Ipv4Addr::from(ip_bytes),
                             subnet: Subnet {
                                 gateway: Ipv4Addr::from(gw_bytes),
-                                mask: Mask(24), // where to get this from?
+                                mask: Mask(prefix_len),
                             },
-                            dns: Some(Ipv4Addr::from(dns_bytes)),
-                            secondary_dns: Some(Ipv4Addr::new(0, 0, 0, 0)),
+                            dns,
+                            secondary_dns,
                         })
                     } else {
                         ClientIpStatus::Waiting
@@ -170,116 +1979,30 @@ impl<'a> embedded_svc::wifi::Wifi for Wifi<'a> {
 
                 Status(
                     ClientStatus::Started(ClientConnectionStatus::Connected(client_ip_status)),
-                    ApStatus::Stopped,
+                    ap_status,
                 )
             }
             crate::wifi::WifiState::StaDisconnected => Status(
                 ClientStatus::Started(ClientConnectionStatus::Disconnected),
-                ApStatus::Stopped,
+                ap_status,
             ),
-            crate::wifi::WifiState::Invalid => Status(ClientStatus::Stopped, ApStatus::Stopped),
+            crate::wifi::WifiState::Invalid => Status(ClientStatus::Stopped, ap_status),
         }
     }
 
     /// A blocking wifi network scan.
+    ///
+    /// `signal_strength` holds the RSSI in dBm as reported by the driver, cast to `u8`. Cast it back
+    /// to `i8` to get the dBm value. Since the RSSI is negative, a higher `signal_strength` still
+    /// means a stronger signal. `scan_raw_n` provides the RSSI as `ApRecord::rssi_dbm`.
+    ///
+    /// `N` only limits the number of returned records, the driver buffers all the APs found
+    /// during the scan until they are fetched. In crowded environments expect some heap usage
+    /// for this while scanning.
     fn scan_n<const N: usize>(
         &mut self,
     ) -> Result<(heapless::Vec<AccessPointInfo, N>, usize), Self::Error> {
-        crate::wifi::wifi_start_scan();
-
-        let mut scanned = heapless::Vec::<AccessPointInfo, N>::new();
-        let mut bss_total: u16 = N as u16;
-
-        unsafe {
-            crate::binary::include::esp_wifi_scan_get_ap_num(&mut bss_total);
-            if bss_total as usize > N {
-                bss_total = N as u16;
-            }
-
-            let mut records = [crate::binary::include::wifi_ap_record_t {
-                bssid: [0u8; 6],
-                ssid: [0u8; 33],
-                primary: 0u8,
-                second: 0u32,
-                rssi: 0i8,
-                authmode: 0u32,
-                pairwise_cipher: 0u32,
-                group_cipher: 0u32,
-                ant: 0u32,
-                _bitfield_align_1: [0u32; 0],
-                _bitfield_1: crate::binary::include::__BindgenBitfieldUnit::new([0u8; 4usize]),
-                country: crate::binary::include::wifi_country_t {
-                    cc: [0; 3],
-                    schan: 0u8,
-                    nchan: 0u8,
-                    max_tx_power: 0i8,
-                    policy: 0u32,
-                },
-            }; N];
-
-            crate::binary::include::esp_wifi_scan_get_ap_records(
-                &mut bss_total,
-                &mut records as *mut crate::binary::include::wifi_ap_record_t,
-            );
-
-            for i in 0..bss_total {
-                let record = records[i as usize];
-                let ssid_strbuf = crate::compat::common::StrBuf::from(&record.ssid as *const u8);
-
-                let auth_method = match record.authmode {
-                    crate::binary::include::wifi_auth_mode_t_WIFI_AUTH_OPEN => AuthMethod::None,
-                    crate::binary::include::wifi_auth_mode_t_WIFI_AUTH_WEP => AuthMethod::WEP,
-                    crate::binary::include::wifi_auth_mode_t_WIFI_AUTH_WPA_PSK => AuthMethod::WPA,
-                    crate::binary::include::wifi_auth_mode_t_WIFI_AUTH_WPA2_PSK => {
-                        AuthMethod::WPA2Personal
-                    }
-                    crate::binary::include::wifi_auth_mode_t_WIFI_AUTH_WPA_WPA2_PSK => {
-                        AuthMethod::WPAWPA2Personal
-                    }
-                    crate::binary::include::wifi_auth_mode_t_WIFI_AUTH_WPA2_ENTERPRISE => {
-                        AuthMethod::WPA2Enterprise
-                    }
-                    crate::binary::include::wifi_auth_mode_t_WIFI_AUTH_WPA3_PSK => {
-                        AuthMethod::WPA3Personal
-                    }
-                    crate::binary::include::wifi_auth_mode_t_WIFI_AUTH_WPA2_WPA3_PSK => {
-                        AuthMethod::WPA2WPA3Personal
-                    }
-                    crate::binary::include::wifi_auth_mode_t_WIFI_AUTH_WAPI_PSK => {
-                        AuthMethod::WAPIPersonal
-                    }
-                    _ => panic!(),
-                };
-
-                let mut ssid = heapless::String::<32>::new();
-                ssid.push_str(ssid_strbuf.as_str_ref()).ok();
-
-                let ap_info = AccessPointInfo {
-                    ssid: ssid,
-                    bssid: record.bssid,
-                    channel: record.primary,
-                    secondary_channel: match record.second {
-                        crate::binary::include::wifi_second_chan_t_WIFI_SECOND_CHAN_NONE => {
-                            SecondaryChannel::None
-                        }
-                        crate::binary::include::wifi_second_chan_t_WIFI_SECOND_CHAN_ABOVE => {
-                            SecondaryChannel::Above
-                        }
-                        crate::binary::include::wifi_second_chan_t_WIFI_SECOND_CHAN_BELOW => {
-                            SecondaryChannel::Below
-                        }
-                        _ => panic!(),
-                    },
-                    signal_strength: record.rssi.abs() as u8,
-                    protocols: EnumSet::empty(), // TODO
-                    auth_method: auth_method,
-                };
-
-                scanned.push(ap_info).ok();
-            }
-        }
-
-        Ok((scanned, bss_total as usize))
+        self.scan_with_config(ScanConfig::default())
     }
 
     /// Get the currently used configuration.
@@ -287,25 +2010,108 @@ impl<'a> embedded_svc::wifi::Wifi for Wifi<'a> {
         Ok(self.current_config.clone())
     }
 
-    /// Set the configuration and start connecting.
-    /// Currently only `ssid` and `password` is used. Trying anything but `Configuration::Client` will result in a panic!
+    /// Set the configuration.
+    ///
+    /// For `Configuration::Client` the station is configured without connecting, call `connect`
+    /// to start connecting. Currently only `ssid`, `bssid`, `password`, `channel` and whether
+    /// `auth_method` is `AuthMethod::None` is used. Setting a `channel` without a `bssid`
+    /// restricts the scan before connecting to that channel. An empty password or
+    /// `AuthMethod::None` joins an open network, unless a minimum auth method was set via
+    /// `set_min_auth`.
+    ///
+    /// For `Configuration::AccessPoint` the soft-AP is started right away using `ssid`, `password`,
+    /// `channel`, `ssid_hidden`, `max_connections` and `auth_method`. An empty password results in an
+    /// open network. `max_connections` defaults to 255 in `embedded-svc` but the driver supports at
+    /// most 10, more are rejected with `WifiError::InvalidArgument`. While the soft-AP is running
+    /// the network stack is bound to it, there is no DHCP server so the clients need a static
+    /// configuration.
+    ///
+    /// For `Configuration::Mixed` both are configured and the soft-AP is started. The network stack
+    /// stays bound to the station interface so DHCP keeps working there, the soft-AP
+    /// doesn't carry any traffic of the network stack in this mode.
+    ///
+    /// Switching back to `Configuration::Client` stops the soft-AP.
+    /// Trying `Configuration::None` will result in a panic!
     fn set_configuration(
         &mut self,
         conf: &embedded_svc::wifi::Configuration,
     ) -> Result<(), Self::Error> {
+        if let embedded_svc::wifi::Configuration::AccessPoint(ap_conf)
+        | embedded_svc::wifi::Configuration::Mixed(_, ap_conf) = conf
+        {
+            if ap_conf.max_connections > crate::binary::include::ESP_WIFI_MAX_CONN_NUM as u16 {
+                return Err(WifiError::InvalidArgument);
+            }
+        }
+
         self.current_config = conf.clone();
 
+        // fails if enterprise mode isn't enabled, which is fine
+        crate::wifi::wifi_enterprise_disable();
+
+        let mut mac = [0u8; 6];
         let res = match conf {
             embedded_svc::wifi::Configuration::None => panic!(),
             embedded_svc::wifi::Configuration::Client(conf) => {
-                crate::wifi::wifi_connect(&conf.ssid, &conf.password)
+                crate::wifi::wifi_get_mac(WifiInterface::Sta, &mut mac);
+                let res =
+                    crate::wifi::wifi_set_mode(crate::binary::include::wifi_mode_t_WIFI_MODE_STA);
+                if res != 0 {
+                    return Err(WifiError::from_raw(res));
+                }
+
+                crate::wifi::wifi_set_sta_config(
+                    &conf.ssid,
+                    client_password(conf),
+                    conf.channel.unwrap_or(0),
+                    conf.bssid,
+                )
+            }
+            embedded_svc::wifi::Configuration::AccessPoint(conf) => {
+                crate::wifi::wifi_get_mac(WifiInterface::Ap, &mut mac);
+                crate::wifi::wifi_start_ap(
+                    &conf.ssid,
+                    &conf.password,
+                    conf.channel,
+                    conf.max_connections as u8,
+                    auth_method_to_raw(conf.auth_method),
+                    conf.ssid_hidden,
+                )
+            }
+            embedded_svc::wifi::Configuration::Mixed(sta_conf, ap_conf) => {
+                crate::wifi::wifi_get_mac(WifiInterface::Sta, &mut mac);
+                let res =
+                    crate::wifi::wifi_set_mode(crate::binary::include::wifi_mode_t_WIFI_MODE_APSTA);
+                if res != 0 {
+                    return Err(WifiError::from_raw(res));
+                }
+
+                let res = crate::wifi::wifi_set_ap_config(
+                    &ap_conf.ssid,
+                    &ap_conf.password,
+                    ap_conf.channel,
+                    ap_conf.max_connections as u8,
+                    auth_method_to_raw(ap_conf.auth_method),
+                    ap_conf.ssid_hidden,
+                );
+                if res != 0 {
+                    return Err(WifiError::from_raw(res));
+                }
+
+                crate::wifi::wifi_set_sta_config(
+                    &sta_conf.ssid,
+                    client_password(sta_conf),
+                    sta_conf.channel.unwrap_or(0),
+                    sta_conf.bssid,
+                )
             }
-            embedded_svc::wifi::Configuration::AccessPoint(_) => panic!(),
-            embedded_svc::wifi::Configuration::Mixed(_, _) => panic!(),
         };
 
+        self.network_interface
+            .set_hardware_addr(HardwareAddress::Ethernet(EthernetAddress(mac)));
+
         if res != 0 {
-            Err(WifiError::Unknown(res))
+            Err(WifiError::from_raw(res))
         } else {
             Ok(())
         }
@@ -320,10 +2126,36 @@ pub fn timestamp() -> Instant {
 // It's currently more or less just here for the DHCP example.
 // Might get replaced or improved in future.
 
+/// Range of the local ports used for outgoing connections (the IANA ephemeral port range).
+const LOCAL_PORT_MIN: u16 = 49152;
+const LOCAL_PORT_MAX: u16 = 65535;
+
+/// Pick a random port to start handing out local ports from.
+///
+/// This avoids reusing the same ports in the same order after every reset which confuses
+/// NATs and firewalls still holding on to the old connections.
+fn initial_local_port(current_millis_fn: fn() -> u64) -> u16 {
+    // the RNG returns 0 if it isn't initialized yet, fall back to the time then
+    let random = unsafe { crate::common_adapter::random() } as u64 ^ current_millis_fn();
+    LOCAL_PORT_MIN + (random % (LOCAL_PORT_MAX - LOCAL_PORT_MIN + 1) as u64) as u16
+}
+
+/// The smallest MTU every IPv4 host has to support (RFC 791).
+const MIN_MTU: usize = 576;
+
+/// The size of the IPv4 and TCP headers without options.
+const TCP_IP_HEADER_LEN: u16 = 40;
+
+/// Maximum number of TCP and UDP sockets a `Network` hands out at the same time.
+const MAX_USED_SOCKETS: usize = 16;
+
 pub struct Network<'a> {
     interface: RefCell<crate::wifi_interface::Wifi<'a>>,
     current_millis_fn: fn() -> u64,
     local_port: RefCell<u16>,
+    used_sockets: RefCell<heapless::Vec<SocketHandle, MAX_USED_SOCKETS>>,
+    dns_timeout_ms: u64,
+    dns_retries: u8,
 }
 
 impl<'a> Network<'a> {
@@ -334,59 +2166,702 @@ impl<'a> Network<'a> {
         Self {
             interface: RefCell::new(interface),
             current_millis_fn,
-            local_port: RefCell::new(41000),
+            local_port: RefCell::new(initial_local_port(current_millis_fn)),
+            used_sockets: RefCell::new(heapless::Vec::new()),
+            dns_timeout_ms: 2000,
+            dns_retries: 2,
+        }
+    }
+
+    /// Configure how long `resolve` waits for an answer to a query and how often
+    /// an unanswered query is sent again.
+    pub fn set_dns_timeout(&mut self, timeout_ms: u64, retries: u8) {
+        self.dns_timeout_ms = timeout_ms;
+        self.dns_retries = retries;
+    }
+
+    /// Resolve `hostname` to an IPv4 address using the DNS server provided by DHCP.
+    ///
+    /// If there is no answer within the timeout the query is sent again. When all the retries
+    /// are used up `DnsError::Timeout` is returned, a name which doesn't exist results in
+    /// `DnsError::NameNotFound`.
+    pub fn resolve(&self, hostname: &str) -> Result<Ipv4Addr, WifiError> {
+        let dns_server = self
+            .with_interface(|interface| interface.dns_server())
+            .ok_or(DnsError::NoServer)?;
+        let mut query = [0u8; 512];
+        // a random id makes spoofing an answer harder
+        let id = unsafe { crate::common_adapter::random() } as u16;
+        let query_len = dns::encode_query(id, hostname, &mut query)?;
+
+        let handle = self.allocate_udp_socket().ok_or(DnsError::NoSocket)?;
+
+        let local_port = self.next_local_port();
+        let res = self
+            .with_interface(|interface| {
+                interface
+                    .network_interface()
+                    .get_socket::<SmolUdpSocket>(handle)
+                    .bind(local_port)
+            })
+            .map_err(WifiError::from)
+            .and_then(|_| self.dns_query(handle, dns_server, id, &query[..query_len]));
+
+        self.release_udp_socket(handle);
+
+        res
+    }
+
+    fn dns_query(
+        &self,
+        handle: SocketHandle,
+        dns_server: Ipv4Address,
+        id: u16,
+        query: &[u8],
+    ) -> Result<Ipv4Addr, WifiError> {
+        let endpoint = IpEndpoint::new(IpAddress::Ipv4(dns_server), dns::DNS_PORT);
+        let mut response = [0u8; 512];
+
+        for _ in 0..=self.dns_retries {
+            self.with_interface(|interface| {
+                interface
+                    .network_interface()
+                    .get_socket::<SmolUdpSocket>(handle)
+                    .send_slice(query, endpoint)
+            })?;
+
+            let deadline = (self.current_millis_fn)() + self.dns_timeout_ms;
+            while (self.current_millis_fn)() < deadline {
+                self.work();
+
+                let received = self.with_interface(|interface| {
+                    interface
+                        .network_interface()
+                        .get_socket::<SmolUdpSocket>(handle)
+                        .recv_slice(&mut response)
+                        .ok()
+                });
+
+                if let Some((len, _)) = received {
+                    if let Some(address) = dns::parse_response(id, &response[..len])? {
+                        return Ok(Ipv4Addr::from(address.0));
+                    }
+                }
+            }
+        }
+
+        Err(DnsError::Timeout.into())
+    }
+
+    /// Find a UDP socket which isn't bound nor in use and mark it as used.
+    fn allocate_udp_socket(&self) -> Option<SocketHandle> {
+        let mut used_sockets = self.used_sockets.borrow_mut();
+        if used_sockets.is_full() {
+            return None;
         }
+
+        let socket_handle = self.with_interface(|interface| {
+            interface
+                .network_interface()
+                .sockets_mut()
+                .find(|(handle, socket)| match socket {
+                    smoltcp::socket::Socket::Udp(udp) => {
+                        !udp.is_open() && !used_sockets.contains(handle)
+                    }
+                    _ => false,
+                })
+                .map(|(handle, _)| handle)
+        })?;
+
+        // can't fail, checked above
+        used_sockets.push(socket_handle).ok();
+        Some(socket_handle)
     }
 
+    /// Unbind a UDP socket and make it available again.
+    fn release_udp_socket(&self, socket_handle: SocketHandle) {
+        self.try_with_interface(|interface| {
+            interface
+                .network_interface()
+                .get_socket::<SmolUdpSocket>(socket_handle)
+                .close()
+        })
+        .ok();
+        self.release_socket(socket_handle);
+    }
+
+    /// Run `f` with exclusive access to the wrapped `Wifi`.
+    ///
+    /// Calls into `Network` must not be nested (e.g. from within `f`), this will panic.
+    /// Use `try_with_interface` where nesting can't be ruled out.
     fn with_interface<F, R>(&self, f: F) -> R
     where
         F: FnOnce(&mut crate::wifi_interface::Wifi<'a>) -> R,
     {
-        let mut interface = self.interface.borrow_mut();
+        let mut interface = self
+            .interface
+            .try_borrow_mut()
+            .expect("nested access to the network interface");
         f(&mut interface)
     }
 
-    pub fn get_socket<'s>(&'s mut self) -> Socket<'s, 'a>
+    /// Run `f` with exclusive access to the wrapped `Wifi`.
+    ///
+    /// Returns `WifiError::InterfaceBusy` instead of panicking if the interface is
+    /// already in use, e.g. when called from within another access to it.
+    pub fn try_with_interface<F, R>(&self, f: F) -> Result<R, WifiError>
+    where
+        F: FnOnce(&mut crate::wifi_interface::Wifi<'a>) -> R,
+    {
+        let mut interface = self
+            .interface
+            .try_borrow_mut()
+            .map_err(|_| WifiError::InterfaceBusy)?;
+        Ok(f(&mut interface))
+    }
+
+    /// Get a TCP socket which isn't in use.
+    ///
+    /// Returns `IoError::NoSocket` if all the TCP sockets are handed out already.
+    /// Dropping the `Socket` aborts its connection and makes it available again.
+    pub fn get_socket<'s>(&'s self) -> Result<Socket<'s, 'a>, IoError>
     where
         'a: 's,
     {
+        let socket_handle = self.allocate_tcp_socket().ok_or(IoError::NoSocket)?;
+
+        Ok(Socket {
+            socket_handle,
+            network: self,
+            io_stats: IoStats::default(),
+            timeout_ms: None,
+        })
+    }
+
+    /// Find a TCP socket which isn't in use and mark it as used.
+    fn allocate_tcp_socket(&self) -> Option<SocketHandle> {
+        let mut used_sockets = self.used_sockets.borrow_mut();
+        if used_sockets.is_full() {
+            return None;
+        }
+
         let socket_handle = self.with_interface(|interface| {
-            let (socket_handle, _) = interface.network_interface().sockets_mut().next().unwrap();
-            socket_handle
-        });
+            interface
+                .network_interface()
+                .sockets_mut()
+                .find(|(handle, socket)| {
+                    matches!(socket, smoltcp::socket::Socket::Tcp(_))
+                        && !used_sockets.contains(handle)
+                })
+                .map(|(handle, _)| handle)
+        })?;
+
+        // can't fail, checked above
+        used_sockets.push(socket_handle).ok();
+        Some(socket_handle)
+    }
+
+    /// Make a socket available to `get_socket` or `get_udp_socket` again.
+    fn release_socket(&self, socket_handle: SocketHandle) {
+        self.used_sockets
+            .borrow_mut()
+            .retain(|handle| *handle != socket_handle);
+    }
 
-        Socket {
+    /// Get a UDP socket which isn't in use.
+    ///
+    /// Returns `IoError::NoSocket` if all the UDP sockets are handed out already. `resolve`,
+    /// `send_broadcast` and `Sntp::sync` need a UDP socket, too. With the single UDP socket
+    /// `create_network_interface` provides they fail with `NoSocket` while this one exists.
+    /// Dropping the `UdpSocket` unbinds it and makes it available again.
+    pub fn get_udp_socket<'s>(&'s self) -> Result<UdpSocket<'s, 'a>, IoError>
+    where
+        'a: 's,
+    {
+        let socket_handle = self.allocate_udp_socket().ok_or(IoError::NoSocket)?;
+
+        Ok(UdpSocket {
             socket_handle,
             network: self,
+        })
+    }
+
+    /// Get a socket sending and receiving IPv4 packets of the given protocol number.
+    ///
+    /// The raw socket takes the slot of a closed TCP socket which isn't in use until it's
+    /// dropped. The buffers are owned by the socket from then on.
+    pub fn get_raw_socket<'s>(
+        &'s self,
+        protocol: u8,
+        rx_buffer: RawSocketBuffer<'a>,
+        tx_buffer: RawSocketBuffer<'a>,
+    ) -> Result<RawSocket<'s, 'a>, IoError>
+    where
+        'a: 's,
+    {
+        let raw_socket = SmolRawSocket::new(
+            IpVersion::Ipv4,
+            IpProtocol::from(protocol),
+            rx_buffer,
+            tx_buffer,
+        );
+        let slot = self.borrow_tcp_slot(raw_socket).ok_or(IoError::NoSocket)?;
+
+        Ok(RawSocket { slot, protocol })
+    }
+
+    /// Put `socket` into the slot of a closed TCP socket which isn't in use.
+    ///
+    /// Returns `None` if there is no such TCP socket. It's put back once the returned
+    /// `BorrowedTcpSlot` is dropped.
+    fn borrow_tcp_slot<'s, T>(&'s self, socket: T) -> Option<BorrowedTcpSlot<'s, 'a>>
+    where
+        T: AnySocket<'a>,
+    {
+        let tcp_handle = self.allocate_tcp_socket()?;
+
+        let tcp_socket = self.with_interface(|interface| {
+            let interface = interface.network_interface();
+            if interface.get_socket::<TcpSocket>(tcp_handle).is_open() {
+                return None;
+            }
+
+            match interface.remove_socket(tcp_handle) {
+                smoltcp::socket::Socket::Tcp(tcp_socket) => Some(tcp_socket),
+                // `allocate_tcp_socket` only hands out TCP sockets
+                _ => unreachable!(),
+            }
+        });
+
+        let tcp_socket = match tcp_socket {
+            Some(tcp_socket) => tcp_socket,
+            None => {
+                self.release_socket(tcp_handle);
+                return None;
+            }
+        };
+
+        let handle =
+            self.with_interface(|interface| interface.network_interface().add_socket(socket));
+
+        Some(BorrowedTcpSlot {
+            network: self,
+            handle,
+            tcp_handle,
+            tcp_socket: Some(tcp_socket),
+        })
+    }
+
+    /// Send `data` as a single UDP datagram to `port` of all hosts on the local network.
+    ///
+    /// The datagram is sent to 255.255.255.255 from an unbound UDP socket which is closed again
+    /// afterwards, so no answers are received. Needs an IPv4 address to send from,
+    /// `WifiError::NotConnected` is returned before one is assigned.
+    pub fn send_broadcast(&self, port: u16, data: &[u8]) -> Result<(), WifiError> {
+        let has_ip = self.with_interface(|interface| {
+            interface
+                .network_interface()
+                .ipv4_addr()
+                .map_or(false, |ip| !ip.is_unspecified())
+        });
+        if !has_ip {
+            return Err(WifiError::NotConnected);
+        }
+
+        let handle = self.allocate_udp_socket().ok_or(WifiError::NoSocket)?;
+
+        let local_port = self.next_local_port();
+        let endpoint = IpEndpoint::new(IpAddress::Ipv4(Ipv4Address::BROADCAST), port);
+        let res = self
+            .with_interface(|interface| {
+                let socket = interface
+                    .network_interface()
+                    .get_socket::<SmolUdpSocket>(handle);
+                socket.bind(local_port)?;
+                socket.send_slice(data, endpoint)
+            })
+            // closing the socket drops the datagram if it's still queued
+            .and_then(|_| self.poll());
+
+        self.release_udp_socket(handle);
+
+        Ok(res?)
+    }
+
+    /// Send `count` ICMP echo requests to `addr`, one after another.
+    ///
+    /// Each request waits at most `timeout_ms` for its reply before the next one is sent.
+    /// A request which can't be queued because the previous one wasn't sent yet counts as lost.
+    /// The ICMP socket temporarily takes the slot of a TCP socket which isn't in use,
+    /// `WifiError::NoSocket` is returned if there is none.
+    pub fn ping(
+        &self,
+        addr: Ipv4Address,
+        count: u16,
+        timeout_ms: u64,
+    ) -> Result<PingStats, WifiError> {
+        static mut ICMP_RX_METADATA: [IcmpPacketMetadata; 1] = [IcmpPacketMetadata::EMPTY; 1];
+        static mut ICMP_RX_DATA: [u8; 256] = [0; 256];
+        static mut ICMP_TX_METADATA: [IcmpPacketMetadata; 1] = [IcmpPacketMetadata::EMPTY; 1];
+        static mut ICMP_TX_DATA: [u8; 256] = [0; 256];
+
+        let ident = (self.current_millis_fn)() as u16;
+        let (rx_buffer, tx_buffer) = unsafe {
+            (
+                IcmpSocketBuffer::new(&mut ICMP_RX_METADATA[..], &mut ICMP_RX_DATA[..]),
+                IcmpSocketBuffer::new(&mut ICMP_TX_METADATA[..], &mut ICMP_TX_DATA[..]),
+            )
+        };
+        let mut icmp_socket = IcmpSocket::new(rx_buffer, tx_buffer);
+        // can't fail, the endpoint is specified and the socket isn't bound yet
+        icmp_socket.bind(IcmpEndpoint::Ident(ident)).ok();
+
+        let slot = self
+            .borrow_tcp_slot(icmp_socket)
+            .ok_or(WifiError::NoSocket)?;
+
+        let mut stats = PingStats::default();
+        let mut res = Ok(());
+        for seq_no in 0..count {
+            match self.echo(slot.handle, addr, ident, seq_no, timeout_ms) {
+                Ok(Some(rtt)) => {
+                    stats.received += 1;
+                    stats.min_rtt_ms = Some(stats.min_rtt_ms.map_or(rtt, |min| min.min(rtt)));
+                    stats.max_rtt_ms = Some(stats.max_rtt_ms.map_or(rtt, |max| max.max(rtt)));
+                    stats.avg_rtt_ms = Some(
+                        (stats.avg_rtt_ms.unwrap_or(0) * (stats.received as u64 - 1) + rtt)
+                            / stats.received as u64,
+                    );
+                }
+                Ok(None) => (),
+                Err(err) => {
+                    res = Err(err);
+                    break;
+                }
+            }
+            stats.sent += 1;
+        }
+
+        res.map(|_| stats)
+    }
+
+    /// Send a single echo request and wait for the reply.
+    ///
+    /// Returns the round trip time or `None` if there was no reply within `timeout_ms`.
+    fn echo(
+        &self,
+        handle: SocketHandle,
+        addr: Ipv4Address,
+        ident: u16,
+        seq_no: u16,
+        timeout_ms: u64,
+    ) -> Result<Option<u64>, WifiError> {
+        const PING_DATA: &[u8] = b"esp-wifi ping";
+        let checksum_caps = smoltcp::phy::ChecksumCapabilities::default();
+
+        let res = self.with_interface(|interface| {
+            let socket = interface
+                .network_interface()
+                .get_socket::<IcmpSocket>(handle);
+            let request = Icmpv4Repr::EchoRequest {
+                ident,
+                seq_no,
+                data: PING_DATA,
+            };
+            let buffer = socket.send(request.buffer_len(), IpAddress::Ipv4(addr))?;
+            request.emit(&mut Icmpv4Packet::new_unchecked(buffer), &checksum_caps);
+            Ok::<(), smoltcp::Error>(())
+        });
+
+        match res {
+            // the previous request is still queued, e.g. because the address isn't resolved yet
+            Err(smoltcp::Error::Exhausted) => {
+                log::debug!("echo request {} not queued, counting it as lost", seq_no);
+                self.work();
+                return Ok(None);
+            }
+            res => res?,
+        }
+
+        let sent_at = (self.current_millis_fn)();
+        while (self.current_millis_fn)() < sent_at + timeout_ms {
+            self.work();
+
+            let replied = self.with_interface(|interface| {
+                let socket = interface
+                    .network_interface()
+                    .get_socket::<IcmpSocket>(handle);
+
+                let mut replied = false;
+                while let Ok((payload, _)) = socket.recv() {
+                    let reply = Icmpv4Packet::new_checked(payload)
+                        .and_then(|packet| Icmpv4Repr::parse(&packet, &checksum_caps));
+                    if let Ok(Icmpv4Repr::EchoReply {
+                        ident: reply_ident,
+                        seq_no: reply_seq_no,
+                        ..
+                    }) = reply
+                    {
+                        replied |= reply_ident == ident && reply_seq_no == seq_no;
+                    }
+                }
+                replied
+            });
+
+            if replied {
+                return Ok(Some((self.current_millis_fn)() - sent_at));
+            }
         }
+
+        Ok(None)
     }
 
     pub fn work(&self) {
         loop {
             self.with_interface(|interface| interface.poll_dhcp().ok());
-            if let Ok(false) = self.with_interface(|interface| {
-                interface
-                    .network_interface()
-                    .poll(Instant::from_millis((self.current_millis_fn)() as i64))
-            }) {
-                break;
+            match self.poll_once() {
+                Ok(true) => continue,
+                Ok(false) => break,
+                Err(err) => {
+                    log::warn!("interface poll failed: {:?}", err);
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Wait until any socket might need servicing, for at most `timeout_ms`.
+    ///
+    /// Returns `true` as soon as polling the interface made progress and `false` if the timeout expired.
+    /// Between polls the interface is only polled again when the delay reported by `poll_delay`
+    /// elapsed or a frame was received.
+    pub fn wait_for_event(&self, timeout_ms: u64) -> bool {
+        let deadline = (self.current_millis_fn)() + timeout_ms;
+
+        loop {
+            self.with_interface(|interface| interface.poll_dhcp().ok());
+            if let Ok(true) = self.poll_once() {
+                return true;
+            }
+
+            if (self.current_millis_fn)() >= deadline {
+                return false;
+            }
+
+            self.idle(Some(deadline));
+        }
+    }
+
+    /// Get the time at which the interface has to be polled next, e.g. to retransmit a TCP segment.
+    ///
+    /// `None` means there is nothing to do until a frame is received, see `crate::wifi::rx_pending`.
+    /// This allows to drive `work` from a timer of the application instead of calling it in a loop.
+    pub fn poll_at(&self) -> Option<Instant> {
+        let now = Instant::from_millis((self.current_millis_fn)() as i64);
+        self.with_interface(|interface| interface.network_interface().poll_at(now))
+    }
+
+    /// Sleep until a frame was received, the delay reported by `poll_delay` elapsed or `deadline`
+    /// passed.
+    ///
+    /// The CPU waits for interrupts in between, the driver tasks keep running.
+    fn idle(&self, deadline: Option<u64>) {
+        let now = (self.current_millis_fn)();
+        let delay = self.with_interface(|interface| {
+            interface
+                .network_interface()
+                .poll_delay(Instant::from_millis(now as i64))
+        });
+        let wake_at = match (delay, deadline) {
+            (Some(delay), Some(deadline)) => Some(deadline.min(now + delay.total_millis())),
+            (Some(delay), None) => Some(now + delay.total_millis()),
+            (None, deadline) => deadline,
+        };
+
+        while wake_at.map_or(true, |wake_at| (self.current_millis_fn)() < wake_at)
+            && !crate::wifi::rx_pending()
+        {
+            crate::wait_for_interrupt();
+        }
+    }
+
+    /// Poll the interface once.
+    fn poll_once(&self) -> Result<bool, smoltcp::Error> {
+        self.with_interface(|interface| {
+            interface
+                .network_interface()
+                .poll(Instant::from_millis((self.current_millis_fn)() as i64))
+        })
+    }
+
+    /// Poll the interface until there is nothing left to process.
+    ///
+    /// Polling continues as long as `poll` reports progress (`Ok(true)`), stops on `Ok(false)`
+    /// and an error is returned instead of polling again.
+    fn poll(&self) -> Result<(), smoltcp::Error> {
+        loop {
+            match self.poll_once() {
+                Ok(true) => continue,
+                Ok(false) => break Ok(()),
+                Err(err) => break Err(err),
             }
         }
     }
 
+    /// Receive from the TCP socket `socket_handle` without polling the interface.
+    ///
+    /// `Ok(0)` is returned once the remote side closed the connection and all data is read.
+    fn tcp_receive(
+        &self,
+        socket_handle: SocketHandle,
+        buf: &mut [u8],
+    ) -> nb::Result<usize, IoError> {
+        self.with_interface(|interface| {
+            let socket = interface
+                .network_interface()
+                .get_socket::<TcpSocket>(socket_handle);
+
+            if socket.can_recv() {
+                return socket
+                    .recv_slice(buf)
+                    .map_err(|e| nb::Error::Other(IoError::Other(e)));
+            }
+
+            if !socket.is_open() {
+                return Err(nb::Error::Other(IoError::SocketClosed));
+            }
+
+            if !socket.may_recv() {
+                return Ok(0);
+            }
+
+            Err(nb::Error::WouldBlock)
+        })
+    }
+
+    /// Get the next local port which isn't used by an open TCP or UDP socket.
     fn next_local_port(&self) -> u16 {
         let mut local_port = self.local_port.borrow_mut();
-        *local_port += 1;
-        if *local_port == 65535 {
-            *local_port = 41000;
+        for _ in LOCAL_PORT_MIN..=LOCAL_PORT_MAX {
+            *local_port = if *local_port >= LOCAL_PORT_MAX {
+                LOCAL_PORT_MIN
+            } else {
+                *local_port + 1
+            };
+
+            if !self.is_port_in_use(*local_port) {
+                break;
+            }
         }
         *local_port
     }
+
+    /// Check if an open TCP or UDP socket uses `port` as its local port.
+    fn is_port_in_use(&self, port: u16) -> bool {
+        self.with_interface(|interface| {
+            interface
+                .network_interface()
+                .sockets_mut()
+                .any(|(_, socket)| match socket {
+                    smoltcp::socket::Socket::Tcp(tcp) => {
+                        tcp.is_open() && tcp.local_endpoint().port == port
+                    }
+                    smoltcp::socket::Socket::Udp(udp) => {
+                        udp.is_open() && udp.endpoint().port == port
+                    }
+                    _ => false,
+                })
+        })
+    }
 }
 
+/// A TCP socket of a `Network`.
+///
+/// Retransmission timing is handled by smoltcp. Its RTT estimator starts at 300 ms and adapts to
+/// the measured round trip times, the initial values can't be configured with smoltcp 0.8.
+/// On links with a much higher RTT expect some spurious retransmits right after connecting.
 pub struct Socket<'s, 'n: 's> {
     socket_handle: SocketHandle,
     network: &'s Network<'n>,
+    io_stats: IoStats,
+    timeout_ms: Option<u64>,
+}
+
+impl<'s, 'n: 's> Drop for Socket<'s, 'n> {
+    fn drop(&mut self) {
+        self.network
+            .try_with_interface(|interface| {
+                interface
+                    .network_interface()
+                    .get_socket::<TcpSocket>(self.socket_handle)
+                    .abort()
+            })
+            .ok();
+        self.network.release_socket(self.socket_handle);
+    }
+}
+
+/// A change of the DHCP state reported by `Wifi::take_dhcp_event`.
+#[derive(Debug, Clone, Copy)]
+pub enum DhcpEvent {
+    /// An address was assigned or the configuration changed
+    Configured {
+        address: Ipv4Cidr,
+        router: Option<Ipv4Address>,
+        dns_servers: [Option<Ipv4Address>; 3],
+        /// The lease time sent by the server
+        lease_time: Option<Duration>,
+    },
+    /// The lease was renewed without changing the configuration
+    Renewed { lease_time: Option<Duration> },
+    /// The address was lost
+    Deconfigured,
+}
+
+/// Progress of DHCP, see `Wifi::dhcp_state`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DhcpState {
+    /// DHCP isn't used, because of a static IP or because the network stack is bound to the soft-AP
+    Disabled,
+    /// There is no address yet
+    Discovering,
+    /// An address is assigned
+    Bound,
+    /// An address is assigned and the lease is being renewed
+    Renewing,
+}
+
+/// The current DHCP lease, see `Wifi::dhcp_lease_info`.
+///
+/// The timers are `None` if the server didn't send a lease time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DhcpLease {
+    pub address: Ipv4Cidr,
+    /// The lease time sent by the server
+    pub lease_time: Option<Duration>,
+    /// Time left until the lease is renewed, zero while renewing
+    pub renew_in: Option<Duration>,
+    /// Time left until the lease expires
+    pub expires_in: Option<Duration>,
+}
+
+/// Result of `Network::ping`.
+///
+/// The round trip times are `None` if no reply was received.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct PingStats {
+    pub sent: u16,
+    pub received: u16,
+    pub min_rtt_ms: Option<u64>,
+    pub avg_rtt_ms: Option<u64>,
+    pub max_rtt_ms: Option<u64>,
+}
+
+/// Number of bytes transferred through a `Socket`.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct IoStats {
+    pub bytes_read: u64,
+    pub bytes_written: u64,
 }
 
 impl<'s, 'n: 's> Socket<'s, 'n> {
@@ -394,17 +2869,41 @@ impl<'s, 'n: 's> Socket<'s, 'n> {
     where
         's: 'i,
     {
-        {
-            self.network.with_interface(|interface| {
+        let local_port = self.network.next_local_port();
+        self.connect(local_port, addr, port)
+    }
+
+    /// Like `open` but connect from the given `local_port` instead of an ephemeral port.
+    ///
+    /// Returns `IoError::PortInUse` if another open socket uses `local_port` already.
+    pub fn open_from<'i>(
+        &'i mut self,
+        local_port: u16,
+        addr: Ipv4Address,
+        port: u16,
+    ) -> Result<(), IoError>
+    where
+        's: 'i,
+    {
+        if self.network.is_port_in_use(local_port) {
+            return Err(IoError::PortInUse);
+        }
+
+        self.connect(local_port, addr, port)
+    }
+
+    fn connect(&mut self, local_port: u16, addr: Ipv4Address, port: u16) -> Result<(), IoError> {
+        self.network
+            .with_interface(|interface| {
                 let (sock, cx) = interface
                     .network_interface()
                     .get_socket_and_context::<TcpSocket>(self.socket_handle);
                 let remote_endpoint = (addr, port);
-                sock.connect(cx, remote_endpoint, self.network.next_local_port())
-                    .unwrap();
-            });
-        }
+                sock.connect(cx, remote_endpoint, local_port)
+            })
+            .map_err(IoError::ConnectFailed)?;
 
+        let deadline = self.deadline();
         loop {
             let can_send = self.network.with_interface(|interface| {
                 let sock = interface
@@ -421,12 +2920,164 @@ impl<'s, 'n: 's> Socket<'s, 'n> {
                 break;
             }
 
+            if self.is_expired(deadline) {
+                self.disconnect();
+                return Err(IoError::TimedOut);
+            }
+
+            self.network.idle(deadline);
             self.work();
         }
 
         Ok(())
     }
 
+    /// Set how long `open`, `accept`, `read`, `write` and `flush` wait before giving up with
+    /// `IoError::TimedOut`.
+    ///
+    /// Without a timeout these wait as long as it takes. A connection attempt which timed out is aborted.
+    pub fn set_timeout(&mut self, timeout_ms: Option<u64>) {
+        self.timeout_ms = timeout_ms;
+    }
+
+    /// Enable or disable Nagle's algorithm, it's enabled by default.
+    ///
+    /// With Nagle's algorithm disabled small writes are sent right away instead of being
+    /// held back until the previous data is acknowledged.
+    pub fn set_nagle(&mut self, enabled: bool) {
+        self.network.with_interface(|interface| {
+            interface
+                .network_interface()
+                .get_socket::<TcpSocket>(self.socket_handle)
+                .set_nagle_enabled(enabled)
+        });
+    }
+
+    /// Send a keep-alive segment whenever the connection was idle for `interval`.
+    ///
+    /// Keep-alives are only sent while the interface gets polled, so the interval
+    /// can't be shorter than the time between calls into the socket or `Network::work`.
+    /// Combine it with `set_remote_timeout` to detect a dead peer.
+    pub fn set_keep_alive(&mut self, interval: Option<Duration>) {
+        self.network.with_interface(|interface| {
+            interface
+                .network_interface()
+                .get_socket::<TcpSocket>(self.socket_handle)
+                .set_keep_alive(interval)
+        });
+    }
+
+    /// Abort the connection if nothing was received from the remote side for `timeout`.
+    ///
+    /// Like the keep-alive this is only checked while the interface gets polled, the socket
+    /// reports `IoError::SocketClosed` afterwards.
+    pub fn set_remote_timeout(&mut self, timeout: Option<Duration>) {
+        self.network.with_interface(|interface| {
+            interface
+                .network_interface()
+                .get_socket::<TcpSocket>(self.socket_handle)
+                .set_timeout(timeout)
+        });
+    }
+
+    fn deadline(&self) -> Option<u64> {
+        self.timeout_ms
+            .map(|timeout_ms| (self.network.current_millis_fn)() + timeout_ms)
+    }
+
+    fn is_expired(&self, deadline: Option<u64>) -> bool {
+        match deadline {
+            Some(deadline) => (self.network.current_millis_fn)() >= deadline,
+            None => false,
+        }
+    }
+
+    /// Listen for incoming connections on the given local port.
+    ///
+    /// A connection left over from a previous `accept` is aborted first, so the socket
+    /// can be used to serve one connection after another.
+    pub fn listen(&mut self, port: u16) -> Result<(), IoError> {
+        self.disconnect();
+
+        self.network.with_interface(|interface| {
+            interface
+                .network_interface()
+                .get_socket::<TcpSocket>(self.socket_handle)
+                .listen(port)
+        })?;
+
+        self.work();
+        Ok(())
+    }
+
+    /// Wait until a client connects to the listening socket.
+    ///
+    /// Returns the endpoint of the client.
+    pub fn accept(&mut self) -> Result<IpEndpoint, IoError> {
+        let deadline = self.deadline();
+        loop {
+            if self.is_expired(deadline) {
+                return Err(IoError::TimedOut);
+            }
+
+            self.work();
+
+            let res = self.network.with_interface(|interface| {
+                let socket = interface
+                    .network_interface()
+                    .get_socket::<TcpSocket>(self.socket_handle);
+
+                match socket.state() {
+                    TcpState::Listen | TcpState::SynReceived => None,
+                    TcpState::Closed => Some(Err(IoError::SocketClosed)),
+                    _ => Some(Ok(socket.remote_endpoint())),
+                }
+            });
+
+            if let Some(res) = res {
+                return res;
+            }
+        }
+    }
+
+    /// Get the local address and port of the connection, `None` if the socket isn't connected.
+    pub fn local_endpoint(&self) -> Option<IpEndpoint> {
+        self.network.with_interface(|interface| {
+            let endpoint = interface
+                .network_interface()
+                .get_socket::<TcpSocket>(self.socket_handle)
+                .local_endpoint();
+            Some(endpoint).filter(|endpoint| endpoint.is_specified())
+        })
+    }
+
+    /// Get the address and port of the remote side, `None` if the socket isn't connected.
+    pub fn remote_endpoint(&self) -> Option<IpEndpoint> {
+        self.network.with_interface(|interface| {
+            let endpoint = interface
+                .network_interface()
+                .get_socket::<TcpSocket>(self.socket_handle)
+                .remote_endpoint();
+            Some(endpoint).filter(|endpoint| endpoint.is_specified())
+        })
+    }
+
+    /// Close the sending side of the connection.
+    ///
+    /// A FIN is sent once all the data written so far is transmitted, reading stays
+    /// possible until the remote side closes the connection as well.
+    /// Unlike `disconnect` this doesn't abort the connection.
+    pub fn close_write(&mut self) {
+        self.network.with_interface(|interface| {
+            interface
+                .network_interface()
+                .get_socket::<TcpSocket>(self.socket_handle)
+                .close();
+        });
+
+        self.work();
+    }
+
     pub fn disconnect(&mut self) {
         self.network.with_interface(|interface| {
             interface
@@ -439,26 +3090,255 @@ impl<'s, 'n: 's> Socket<'s, 'n> {
     }
 
     pub fn work(&mut self) {
+        self.network.work();
+    }
+
+    /// Get the number of bytes read from and written to this socket.
+    pub fn io_stats(&self) -> IoStats {
+        self.io_stats
+    }
+
+    /// Read without blocking.
+    ///
+    /// The interface is polled once, `nb::Error::WouldBlock` is returned if no data is available.
+    /// Once the remote side closed the connection and all data is read `Ok(0)` is returned.
+    /// Use `read` from `embedded_io`'s `Read` for the blocking variant.
+    pub fn try_read(&mut self, buf: &mut [u8]) -> nb::Result<usize, IoError> {
+        self.network.poll_once().map_err(IoError::Other)?;
+
+        let len = self.network.tcp_receive(self.socket_handle, buf)?;
+
+        self.io_stats.bytes_read += len as u64;
+        Ok(len)
+    }
+
+    /// Write without blocking.
+    ///
+    /// The interface is polled once, `nb::Error::WouldBlock` is returned if the transmit buffer is full.
+    /// Returns how many bytes of `buf` were queued which might be less than `buf.len()`.
+    /// Use `write` from `embedded_io`'s `Write` for the blocking variant.
+    pub fn try_write(&mut self, buf: &[u8]) -> nb::Result<usize, IoError> {
+        self.network.poll_once().map_err(IoError::Other)?;
+
+        let len = self.network.with_interface(|interface| {
+            let socket = interface
+                .network_interface()
+                .get_socket::<TcpSocket>(self.socket_handle);
+
+            if !socket.may_send() {
+                return Err(nb::Error::Other(IoError::SocketClosed));
+            }
+
+            if !socket.can_send() {
+                return Err(nb::Error::WouldBlock);
+            }
+
+            socket
+                .send_slice(buf)
+                .map_err(|e| nb::Error::Other(IoError::Other(e)))
+        })?;
+
+        // the data is queued already, a failing poll must not make the caller send it again
+        if let Err(err) = self.network.poll_once() {
+            log::warn!("interface poll failed: {:?}", err);
+        }
+
+        self.io_stats.bytes_written += len as u64;
+        Ok(len)
+    }
+}
+
+/// A UDP socket of a `Network`.
+pub struct UdpSocket<'s, 'n: 's> {
+    socket_handle: SocketHandle,
+    network: &'s Network<'n>,
+}
+
+impl<'s, 'n: 's> UdpSocket<'s, 'n> {
+    /// Bind the socket to the given local port.
+    pub fn bind(&mut self, port: u16) -> Result<(), IoError> {
+        self.network.with_interface(|interface| {
+            interface
+                .network_interface()
+                .get_socket::<SmolUdpSocket>(self.socket_handle)
+                .bind(port)
+        })?;
+
+        Ok(())
+    }
+
+    /// Send a datagram to `endpoint`.
+    pub fn send_to(&mut self, data: &[u8], endpoint: IpEndpoint) -> Result<(), IoError> {
+        self.network.with_interface(|interface| {
+            interface
+                .network_interface()
+                .get_socket::<SmolUdpSocket>(self.socket_handle)
+                .send_slice(data, endpoint)
+        })?;
+
+        self.network.poll()?;
+        Ok(())
+    }
+
+    /// Wait for a datagram and receive it into `buf`.
+    ///
+    /// Returns the length of the datagram and the sender.
+    pub fn recv_from(&mut self, buf: &mut [u8]) -> Result<(usize, IpEndpoint), IoError> {
         loop {
             self.network
                 .with_interface(|interface| interface.poll_dhcp().ok());
-            if let Ok(false) = self.network.with_interface(|interface| {
-                interface
+            self.network.poll()?;
+
+            let res = self.network.with_interface(|interface| {
+                let socket = interface
                     .network_interface()
-                    .poll(Instant::from_millis(
-                        (self.network.current_millis_fn)() as i64
-                    ))
-            }) {
-                break;
+                    .get_socket::<SmolUdpSocket>(self.socket_handle);
+
+                if !socket.is_open() {
+                    return Some(Err(IoError::SocketClosed));
+                }
+
+                if socket.can_recv() {
+                    Some(socket.recv_slice(buf).map_err(|e| IoError::Other(e)))
+                } else {
+                    None
+                }
+            });
+
+            if let Some(res) = res {
+                return res;
             }
         }
     }
+
+    /// Unbind the socket.
+    pub fn close(&mut self) {
+        self.network.with_interface(|interface| {
+            interface
+                .network_interface()
+                .get_socket::<SmolUdpSocket>(self.socket_handle)
+                .close()
+        });
+    }
+
+    pub fn work(&mut self) {
+        self.network.work();
+    }
+}
+
+impl<'s, 'n: 's> Drop for UdpSocket<'s, 'n> {
+    fn drop(&mut self) {
+        self.network.release_udp_socket(self.socket_handle);
+    }
+}
+
+/// A socket of another type in the slot of a TCP socket, see `Network::borrow_tcp_slot`.
+///
+/// Dropping it removes the socket and puts the TCP socket back.
+struct BorrowedTcpSlot<'s, 'n: 's> {
+    network: &'s Network<'n>,
+    handle: SocketHandle,
+    tcp_handle: SocketHandle,
+    tcp_socket: Option<TcpSocket<'n>>,
+}
+
+impl<'s, 'n: 's> Drop for BorrowedTcpSlot<'s, 'n> {
+    fn drop(&mut self) {
+        let tcp_socket = self.tcp_socket.take();
+        self.network
+            .try_with_interface(|interface| {
+                let interface = interface.network_interface();
+                interface.remove_socket(self.handle);
+                if let Some(tcp_socket) = tcp_socket {
+                    interface.add_socket(tcp_socket);
+                }
+            })
+            .ok();
+        self.network.release_socket(self.tcp_handle);
+    }
+}
+
+/// A raw IPv4 socket of a `Network` for a single protocol number.
+///
+/// Only the payload is passed in and out, the IPv4 header is handled by the socket.
+pub struct RawSocket<'s, 'n: 's> {
+    slot: BorrowedTcpSlot<'s, 'n>,
+    protocol: u8,
+}
+
+impl<'s, 'n: 's> RawSocket<'s, 'n> {
+    /// Queue `payload` to be sent to `addr`.
+    pub fn send(&mut self, addr: Ipv4Address, payload: &[u8]) -> Result<(), IoError> {
+        self.slot.network.with_interface(|interface| {
+            let src_addr = interface
+                .network_interface()
+                .ipv4_addr()
+                .filter(|addr| !addr.is_unspecified())
+                .ok_or(smoltcp::Error::Unaddressable)?;
+
+            let repr = Ipv4Repr {
+                src_addr,
+                dst_addr: addr,
+                protocol: IpProtocol::from(self.protocol),
+                payload_len: payload.len(),
+                hop_limit: 64,
+            };
+
+            let sock = interface
+                .network_interface()
+                .get_socket::<SmolRawSocket>(self.slot.handle);
+            let buffer = sock.send(repr.buffer_len() + payload.len())?;
+            let mut packet = Ipv4Packet::new_unchecked(buffer);
+            repr.emit(&mut packet, &smoltcp::phy::ChecksumCapabilities::default());
+            packet.payload_mut().copy_from_slice(payload);
+
+            Ok::<(), smoltcp::Error>(())
+        })?;
+
+        self.slot.network.work();
+        Ok(())
+    }
+
+    /// Receive the payload of the next packet into `buf`.
+    ///
+    /// Returns the sender and the length of the payload or `None` if no packet is pending.
+    /// A payload not fitting into `buf` is truncated.
+    pub fn recv(&mut self, buf: &mut [u8]) -> Result<Option<(Ipv4Address, usize)>, IoError> {
+        self.slot.network.work();
+
+        self.slot
+            .network
+            .with_interface(|interface| -> Result<_, IoError> {
+                let sock = interface
+                    .network_interface()
+                    .get_socket::<SmolRawSocket>(self.slot.handle);
+                if !sock.can_recv() {
+                    return Ok(None);
+                }
+
+                let packet = Ipv4Packet::new_checked(sock.recv()?)?;
+                let payload = packet.payload();
+                let len = payload.len().min(buf.len());
+                buf[..len].copy_from_slice(&payload[..len]);
+
+                Ok(Some((packet.src_addr(), len)))
+            })
+    }
 }
 
 #[derive(Debug)]
 pub enum IoError {
     Other(smoltcp::Error),
     SocketClosed,
+    /// There is no suitable socket available
+    NoSocket,
+    /// The operation didn't complete within the timeout set via `Socket::set_timeout`
+    TimedOut,
+    /// The connection couldn't be started, e.g. because the socket is already open or the
+    /// address or port is invalid
+    ConnectFailed(smoltcp::Error),
+    /// The local port is already used by another socket
+    PortInUse,
 }
 
 impl embedded_io::Error for IoError {
@@ -479,71 +3359,38 @@ impl<'s, 'n: 's> Io for Socket<'s, 'n> {
 
 impl<'s, 'n: 's> Read for Socket<'s, 'n> {
     fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        let deadline = self.deadline();
         loop {
-            self.network.with_interface(|interface| {
-                interface
-                    .network_interface()
-                    .poll(Instant::from_millis(
-                        (self.network.current_millis_fn)() as i64
-                    ))
-                    .unwrap();
-            });
-
-            let (may_recv, is_open, can_recv) = self.network.with_interface(|interface| {
-                let socket = interface
-                    .network_interface()
-                    .get_socket::<TcpSocket>(self.socket_handle);
-
-                (socket.may_recv(), socket.is_open(), socket.can_recv())
-            });
-            if may_recv {
-                break;
-            }
+            // errors of a poll are about single packets, `work` only logs them
+            self.network.work();
 
-            if !is_open {
-                return Err(IoError::SocketClosed);
+            match self.network.tcp_receive(self.socket_handle, buf) {
+                Ok(len) => {
+                    self.io_stats.bytes_read += len as u64;
+                    return Ok(len);
+                }
+                Err(nb::Error::Other(err)) => return Err(err),
+                Err(nb::Error::WouldBlock) => (),
             }
 
-            if !can_recv {
-                return Err(IoError::SocketClosed);
+            if self.is_expired(deadline) {
+                return Err(IoError::TimedOut);
             }
-        }
-
-        loop {
-            let res = self.network.with_interface(|interface| {
-                interface
-                    .network_interface()
-                    .poll(Instant::from_millis(
-                        (self.network.current_millis_fn)() as i64
-                    ))
-            });
 
-            if let Ok(false) = res {
-                break;
-            }
+            self.network.idle(deadline);
         }
-
-        self.network.with_interface(|interface| {
-            let socket = interface
-                .network_interface()
-                .get_socket::<TcpSocket>(self.socket_handle);
-
-            socket.recv_slice(buf).map_err(|e| IoError::Other(e))
-        })
     }
 }
 
 impl<'s, 'n: 's> Write for Socket<'s, 'n> {
     fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        let deadline = self.deadline();
         loop {
-            self.network.with_interface(|interface| {
-                interface
-                    .network_interface()
-                    .poll(Instant::from_millis(
-                        (self.network.current_millis_fn)() as i64
-                    ))
-                    .unwrap();
-            });
+            if self.is_expired(deadline) {
+                return Err(IoError::TimedOut);
+            }
+
+            self.network.work();
 
             let (may_send, is_open, can_send) = self.network.with_interface(|interface| {
                 let socket = interface
@@ -564,62 +3411,84 @@ impl<'s, 'n: 's> Write for Socket<'s, 'n> {
             if !can_send {
                 return Err(IoError::SocketClosed);
             }
+
+            self.network.idle(deadline);
         }
 
-        loop {
+        self.network.work();
+
+        let mut written = 0;
+        let res = loop {
             let res = self.network.with_interface(|interface| {
                 interface
                     .network_interface()
-                    .poll(Instant::from_millis(
-                        (self.network.current_millis_fn)() as i64
-                    ))
+                    .get_socket::<TcpSocket>(self.socket_handle)
+                    .send_slice(&buf[written..])
             });
 
-            if let Ok(false) = res {
-                break;
-            }
-        }
-
-        let res = self.network.with_interface(|interface| {
-            let socket = interface
-                .network_interface()
-                .get_socket::<TcpSocket>(self.socket_handle);
-
-            let mut written = 0;
-            loop {
-                match socket.send_slice(&buf[written..]) {
-                    Ok(len) => {
-                        written += len;
+            match res {
+                Ok(len) => {
+                    written += len;
 
-                        if written >= buf.len() {
-                            break Ok(written);
-                        }
-
-                        log::info!("not fully written: {}", len);
+                    if written >= buf.len() {
+                        break Ok(written);
                     }
-                    Err(err) => break Err(IoError::Other(err)),
+
+                    log::info!("not fully written: {}", len);
                 }
+                Err(err) => break Err(IoError::Other(err)),
             }
-        });
+
+            // the transmit buffer is full, a partial write is returned once the timeout expired
+            if self.is_expired(deadline) {
+                break if written > 0 {
+                    Ok(written)
+                } else {
+                    Err(IoError::TimedOut)
+                };
+            }
+
+            self.network.idle(deadline);
+            self.network.work();
+        };
+
+        if let Ok(written) = res {
+            self.io_stats.bytes_written += written as u64;
+        }
 
         res
     }
 
+    /// Wait until all the data written so far was acknowledged by the remote side.
+    ///
+    /// Gives up with `IoError::TimedOut` after the timeout set via `set_timeout`. Afterwards
+    /// `disconnect` can abort the connection without losing data.
     fn flush(&mut self) -> Result<(), Self::Error> {
+        let deadline = self.deadline();
         loop {
-            let res = self.network.with_interface(|interface| {
-                interface
+            self.network.work();
+
+            let (send_queue, is_open) = self.network.with_interface(|interface| {
+                let socket = interface
                     .network_interface()
-                    .poll(Instant::from_millis(
-                        (self.network.current_millis_fn)() as i64
-                    ))
+                    .get_socket::<TcpSocket>(self.socket_handle);
+
+                (socket.send_queue(), socket.is_open())
             });
 
-            if let Ok(false) = res {
-                break;
+            if send_queue == 0 {
+                return Ok(());
             }
-        }
 
-        Ok(())
+            if !is_open {
+                return Err(IoError::SocketClosed);
+            }
+
+            if self.is_expired(deadline) {
+                return Err(IoError::TimedOut);
+            }
+
+            self.network.idle(deadline);
+        }
     }
 }
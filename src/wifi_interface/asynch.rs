@@ -0,0 +1,160 @@
+//! `embedded-io` async `Read`/`Write` for `Socket`
+//!
+//! A future which can't make progress registers its waker with smoltcp and with the driver.
+//! It's woken once a frame was received, the next poll of the future polls the interface.
+//! Nothing wakes it for the retransmission timers of TCP, if the connection is idle otherwise
+//! call `Network::work` from time to time.
+
+use core::future::{poll_fn, Future};
+use core::task::{Context, Poll};
+
+use embedded_io::asynch::{Read, Write};
+use embedded_io::Io;
+use smoltcp::socket::TcpSocket;
+
+use super::{IoError, IoStats, Socket};
+
+/// A `Socket` which implements the async `Read` and `Write` traits.
+///
+/// Open the socket first via `Socket::open` or `Socket::accept`, the timeout set via
+/// `Socket::set_timeout` doesn't apply.
+pub struct AsyncSocket<'s, 'n: 's> {
+    socket: Socket<'s, 'n>,
+}
+
+impl<'s, 'n: 's> AsyncSocket<'s, 'n> {
+    pub fn new(socket: Socket<'s, 'n>) -> AsyncSocket<'s, 'n> {
+        AsyncSocket { socket }
+    }
+
+    /// Give back the wrapped `Socket`.
+    pub fn into_inner(self) -> Socket<'s, 'n> {
+        self.socket
+    }
+
+    /// Get the number of bytes read from and written to this socket.
+    pub fn io_stats(&self) -> IoStats {
+        self.socket.io_stats()
+    }
+
+    fn poll_read(&mut self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<Result<usize, IoError>> {
+        // register first so a frame received while polling isn't missed
+        crate::wifi::register_rx_waker(cx.waker());
+        self.socket.network.work();
+
+        let res = self.socket.network.with_interface(|interface| {
+            let socket = interface
+                .network_interface()
+                .get_socket::<TcpSocket>(self.socket.socket_handle);
+
+            if socket.can_recv() {
+                return Poll::Ready(socket.recv_slice(buf).map_err(IoError::Other));
+            }
+
+            if !socket.is_open() {
+                return Poll::Ready(Err(IoError::SocketClosed));
+            }
+
+            if !socket.may_recv() {
+                return Poll::Ready(Ok(0));
+            }
+
+            socket.register_recv_waker(cx.waker());
+            Poll::Pending
+        });
+
+        if let Poll::Ready(Ok(len)) = res {
+            self.socket.io_stats.bytes_read += len as u64;
+        }
+
+        res
+    }
+
+    fn poll_write(&mut self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize, IoError>> {
+        crate::wifi::register_rx_waker(cx.waker());
+        self.socket.network.work();
+
+        let res = self.socket.network.with_interface(|interface| {
+            let socket = interface
+                .network_interface()
+                .get_socket::<TcpSocket>(self.socket.socket_handle);
+
+            if !socket.may_send() {
+                return Poll::Ready(Err(IoError::SocketClosed));
+            }
+
+            if !socket.can_send() {
+                socket.register_send_waker(cx.waker());
+                return Poll::Pending;
+            }
+
+            Poll::Ready(socket.send_slice(buf).map_err(IoError::Other))
+        });
+
+        if let Poll::Ready(Ok(len)) = res {
+            self.socket.io_stats.bytes_written += len as u64;
+            self.socket.network.work();
+        }
+
+        res
+    }
+
+    fn poll_flush(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), IoError>> {
+        crate::wifi::register_rx_waker(cx.waker());
+        self.socket.network.work();
+
+        self.socket.network.with_interface(|interface| {
+            let socket = interface
+                .network_interface()
+                .get_socket::<TcpSocket>(self.socket.socket_handle);
+
+            if socket.send_queue() == 0 {
+                return Poll::Ready(Ok(()));
+            }
+
+            if !socket.is_open() {
+                return Poll::Ready(Err(IoError::SocketClosed));
+            }
+
+            // woken once acknowledged data is removed from the transmit buffer
+            socket.register_send_waker(cx.waker());
+            Poll::Pending
+        })
+    }
+}
+
+impl<'s, 'n: 's> Io for AsyncSocket<'s, 'n> {
+    type Error = IoError;
+}
+
+impl<'s, 'n: 's> Read for AsyncSocket<'s, 'n> {
+    type ReadFuture<'a>
+        = impl Future<Output = Result<usize, IoError>>
+    where
+        Self: 'a;
+
+    fn read<'a>(&'a mut self, buf: &'a mut [u8]) -> Self::ReadFuture<'a> {
+        poll_fn(move |cx| self.poll_read(cx, buf))
+    }
+}
+
+impl<'s, 'n: 's> Write for AsyncSocket<'s, 'n> {
+    type WriteFuture<'a>
+        = impl Future<Output = Result<usize, IoError>>
+    where
+        Self: 'a;
+
+    fn write<'a>(&'a mut self, buf: &'a [u8]) -> Self::WriteFuture<'a> {
+        poll_fn(move |cx| self.poll_write(cx, buf))
+    }
+
+    type FlushFuture<'a>
+        = impl Future<Output = Result<(), IoError>>
+    where
+        Self: 'a;
+
+    /// Wait until all the data written so far was acknowledged by the remote side.
+    fn flush<'a>(&'a mut self) -> Self::FlushFuture<'a> {
+        poll_fn(move |cx| self.poll_flush(cx))
+    }
+}
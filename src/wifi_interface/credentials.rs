@@ -0,0 +1,180 @@
+//! Credentials of a client configuration in a fixed binary layout
+//!
+//! The crate doesn't access the flash, store the bytes wherever the application keeps its
+//! settings and restore the configuration from them on boot.
+//!
+//! Layout of the `STORED_CREDENTIALS_LEN` bytes:
+//!
+//! | Offset | Length | Content                                        |
+//! |--------|--------|------------------------------------------------|
+//! | 0      | 1      | `MAGIC`                                        |
+//! | 1      | 1      | Auth method                                    |
+//! | 2      | 1      | Channel, 0 for any channel                     |
+//! | 3      | 1      | 1 if the BSSID is set, 0 otherwise             |
+//! | 4      | 6      | BSSID                                          |
+//! | 10     | 1      | Length of the SSID                             |
+//! | 11     | 32     | SSID, padded with zeros                        |
+//! | 43     | 1      | Length of the password                         |
+//! | 44     | 64     | Password, padded with zeros                    |
+
+use embedded_svc::wifi::{AuthMethod, ClientConfiguration, Configuration};
+
+/// Identifies the layout, erased flash (all 0xff) or zeros don't match it.
+const MAGIC: u8 = 0xc1;
+
+const SSID_LEN: usize = 32;
+const PASSWORD_LEN: usize = 64;
+
+const AUTH_OFFSET: usize = 1;
+const CHANNEL_OFFSET: usize = 2;
+const BSSID_FLAG_OFFSET: usize = 3;
+const BSSID_OFFSET: usize = 4;
+const SSID_OFFSET: usize = 10;
+const PASSWORD_OFFSET: usize = SSID_OFFSET + 1 + SSID_LEN;
+
+/// Number of bytes of stored credentials.
+pub const STORED_CREDENTIALS_LEN: usize = PASSWORD_OFFSET + 1 + PASSWORD_LEN;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CredentialsError {
+    /// The bytes aren't stored credentials, e.g. the flash was never written
+    InvalidMagic,
+    /// The bytes are too short or a field is out of range
+    Malformed,
+}
+
+/// SSID, password and auth method of a client configuration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredCredentials {
+    pub ssid: heapless::String<32>,
+    pub password: heapless::String<64>,
+    pub auth_method: AuthMethod,
+    pub channel: Option<u8>,
+    pub bssid: Option<[u8; 6]>,
+}
+
+impl StoredCredentials {
+    /// Take the credentials of the client part of `config`.
+    ///
+    /// Returns `None` for a pure access point configuration. The IP configuration isn't stored.
+    pub fn from_configuration(config: &Configuration) -> Option<StoredCredentials> {
+        let client = match config {
+            Configuration::Client(client) | Configuration::Mixed(client, _) => client,
+            _ => return None,
+        };
+
+        Some(StoredCredentials {
+            ssid: client.ssid.clone(),
+            password: client.password.clone(),
+            auth_method: client.auth_method,
+            channel: client.channel,
+            bssid: client.bssid,
+        })
+    }
+
+    /// Build a client configuration to pass to `set_configuration`.
+    pub fn to_configuration(&self) -> Configuration {
+        Configuration::Client(ClientConfiguration {
+            ssid: self.ssid.clone(),
+            password: self.password.clone(),
+            auth_method: self.auth_method,
+            channel: self.channel,
+            bssid: self.bssid,
+            ..Default::default()
+        })
+    }
+
+    pub fn to_bytes(&self) -> [u8; STORED_CREDENTIALS_LEN] {
+        let mut bytes = [0u8; STORED_CREDENTIALS_LEN];
+
+        bytes[0] = MAGIC;
+        bytes[AUTH_OFFSET] = auth_method_to_byte(self.auth_method);
+        bytes[CHANNEL_OFFSET] = self.channel.unwrap_or(0);
+        if let Some(bssid) = self.bssid {
+            bytes[BSSID_FLAG_OFFSET] = 1;
+            bytes[BSSID_OFFSET..][..6].copy_from_slice(&bssid);
+        }
+
+        bytes[SSID_OFFSET] = self.ssid.len() as u8;
+        bytes[SSID_OFFSET + 1..][..self.ssid.len()].copy_from_slice(self.ssid.as_bytes());
+
+        bytes[PASSWORD_OFFSET] = self.password.len() as u8;
+        bytes[PASSWORD_OFFSET + 1..][..self.password.len()]
+            .copy_from_slice(self.password.as_bytes());
+
+        bytes
+    }
+
+    /// Parse bytes written by `to_bytes`, additional bytes are ignored.
+    pub fn from_bytes(bytes: &[u8]) -> Result<StoredCredentials, CredentialsError> {
+        if bytes.is_empty() || bytes[0] != MAGIC {
+            return Err(CredentialsError::InvalidMagic);
+        }
+
+        if bytes.len() < STORED_CREDENTIALS_LEN {
+            return Err(CredentialsError::Malformed);
+        }
+
+        let auth_method =
+            auth_method_from_byte(bytes[AUTH_OFFSET]).ok_or(CredentialsError::Malformed)?;
+        let channel = match bytes[CHANNEL_OFFSET] {
+            0 => None,
+            channel => Some(channel),
+        };
+        let bssid = match bytes[BSSID_FLAG_OFFSET] {
+            0 => None,
+            1 => Some(bytes[BSSID_OFFSET..][..6].try_into().unwrap()),
+            _ => return Err(CredentialsError::Malformed),
+        };
+
+        Ok(StoredCredentials {
+            ssid: read_string(&bytes[SSID_OFFSET..][..1 + SSID_LEN])?,
+            password: read_string(&bytes[PASSWORD_OFFSET..][..1 + PASSWORD_LEN])?,
+            auth_method,
+            channel,
+            bssid,
+        })
+    }
+}
+
+/// Read a string prefixed by its length.
+fn read_string<const N: usize>(field: &[u8]) -> Result<heapless::String<N>, CredentialsError> {
+    let len = field[0] as usize;
+    if len > N {
+        return Err(CredentialsError::Malformed);
+    }
+
+    let value =
+        core::str::from_utf8(&field[1..][..len]).map_err(|_| CredentialsError::Malformed)?;
+    Ok(value.into())
+}
+
+// the values are part of the layout, don't use the ones of the driver which might change
+fn auth_method_to_byte(auth_method: AuthMethod) -> u8 {
+    match auth_method {
+        AuthMethod::None => 0,
+        AuthMethod::WEP => 1,
+        AuthMethod::WPA => 2,
+        AuthMethod::WPA2Personal => 3,
+        AuthMethod::WPAWPA2Personal => 4,
+        AuthMethod::WPA2Enterprise => 5,
+        AuthMethod::WPA3Personal => 6,
+        AuthMethod::WPA2WPA3Personal => 7,
+        AuthMethod::WAPIPersonal => 8,
+    }
+}
+
+fn auth_method_from_byte(byte: u8) -> Option<AuthMethod> {
+    Some(match byte {
+        0 => AuthMethod::None,
+        1 => AuthMethod::WEP,
+        2 => AuthMethod::WPA,
+        3 => AuthMethod::WPA2Personal,
+        4 => AuthMethod::WPAWPA2Personal,
+        5 => AuthMethod::WPA2Enterprise,
+        6 => AuthMethod::WPA3Personal,
+        7 => AuthMethod::WPA2WPA3Personal,
+        8 => AuthMethod::WAPIPersonal,
+        _ => return None,
+    })
+}
@@ -0,0 +1,131 @@
+//! Just enough of the DNS wire format to look up A records.
+
+use smoltcp::wire::Ipv4Address;
+
+pub(crate) const DNS_PORT: u16 = 53;
+
+const HEADER_LEN: usize = 12;
+const FLAG_RESPONSE: u16 = 0x8000;
+const FLAG_RECURSION_DESIRED: u16 = 0x0100;
+const RCODE_MASK: u16 = 0x000f;
+const RCODE_NAME_ERROR: u16 = 3;
+const TYPE_A: u16 = 1;
+const CLASS_IN: u16 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DnsError {
+    /// The hostname can't be encoded into a query
+    InvalidName,
+    /// The response couldn't be parsed
+    MalformedResponse,
+    /// The name doesn't exist (NXDOMAIN)
+    NameNotFound,
+    /// The server answered with the given error code
+    ServerError(u8),
+    /// The response doesn't contain an A record
+    NoAddress,
+    /// There was no answer in time
+    Timeout,
+    /// No DNS server is known
+    NoServer,
+    /// There is no unbound UDP socket to send the query from
+    NoSocket,
+}
+
+/// Write an A record query for `name` into `buffer`.
+/// Returns the length of the query.
+pub(crate) fn encode_query(id: u16, name: &str, buffer: &mut [u8]) -> Result<usize, DnsError> {
+    let name = name.trim_end_matches('.');
+    // labels + length octets + root label + QTYPE + QCLASS
+    if name.is_empty() || HEADER_LEN + name.len() + 2 + 4 > buffer.len() {
+        return Err(DnsError::InvalidName);
+    }
+
+    buffer[..HEADER_LEN].fill(0);
+    buffer[0..2].copy_from_slice(&id.to_be_bytes());
+    buffer[2..4].copy_from_slice(&FLAG_RECURSION_DESIRED.to_be_bytes());
+    buffer[4..6].copy_from_slice(&1u16.to_be_bytes());
+
+    let mut pos = HEADER_LEN;
+    for label in name.split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(DnsError::InvalidName);
+        }
+
+        buffer[pos] = label.len() as u8;
+        buffer[pos + 1..][..label.len()].copy_from_slice(label.as_bytes());
+        pos += 1 + label.len();
+    }
+    buffer[pos] = 0;
+    pos += 1;
+
+    buffer[pos..][..2].copy_from_slice(&TYPE_A.to_be_bytes());
+    buffer[pos + 2..][..2].copy_from_slice(&CLASS_IN.to_be_bytes());
+    pos += 4;
+
+    Ok(pos)
+}
+
+/// Parse the response to the query with the given `id`.
+/// Returns `Ok(None)` if `data` isn't a response to that query.
+pub(crate) fn parse_response(id: u16, data: &[u8]) -> Result<Option<Ipv4Address>, DnsError> {
+    if data.len() < HEADER_LEN {
+        return Err(DnsError::MalformedResponse);
+    }
+
+    let flags = read_u16(data, 2)?;
+    if read_u16(data, 0)? != id || flags & FLAG_RESPONSE == 0 {
+        return Ok(None);
+    }
+
+    match flags & RCODE_MASK {
+        0 => (),
+        RCODE_NAME_ERROR => return Err(DnsError::NameNotFound),
+        rcode => return Err(DnsError::ServerError(rcode as u8)),
+    }
+
+    let questions = read_u16(data, 4)?;
+    let answers = read_u16(data, 6)?;
+
+    let mut pos = HEADER_LEN;
+    for _ in 0..questions {
+        pos = skip_name(data, pos)? + 4;
+    }
+
+    for _ in 0..answers {
+        pos = skip_name(data, pos)?;
+        let rtype = read_u16(data, pos)?;
+        let class = read_u16(data, pos + 2)?;
+        let len = read_u16(data, pos + 8)? as usize;
+        pos += 10;
+
+        let rdata = data
+            .get(pos..pos + len)
+            .ok_or(DnsError::MalformedResponse)?;
+        if rtype == TYPE_A && class == CLASS_IN && len == 4 {
+            return Ok(Some(Ipv4Address::from_bytes(rdata)));
+        }
+        pos += len;
+    }
+
+    Err(DnsError::NoAddress)
+}
+
+/// Skip a (possibly compressed) name starting at `pos`, returns the position after it.
+fn skip_name(data: &[u8], mut pos: usize) -> Result<usize, DnsError> {
+    loop {
+        let len = *data.get(pos).ok_or(DnsError::MalformedResponse)?;
+        match len {
+            0 => return Ok(pos + 1),
+            // a pointer always ends the name
+            len if len & 0xc0 == 0xc0 => return Ok(pos + 2),
+            len => pos += 1 + len as usize,
+        }
+    }
+}
+
+fn read_u16(data: &[u8], pos: usize) -> Result<u16, DnsError> {
+    data.get(pos..pos + 2)
+        .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+        .ok_or(DnsError::MalformedResponse)
+}
@@ -0,0 +1,203 @@
+//! `embedded-svc` `EventBus` implementation publishing the `WifiEvent`s
+//!
+//! The subscribed closures are moved to the heap the driver allocates from.
+
+use core::alloc::Layout;
+use core::cell::RefCell;
+use core::ptr::NonNull;
+
+use critical_section::Mutex;
+use embedded_svc::event_bus::{ErrorType, EventBus};
+
+use super::{Wifi, WifiError};
+use crate::wifi::WifiEvent;
+use crate::HEAP;
+
+/// Maximum number of subscriptions existing at the same time.
+const MAX_SUBSCRIBERS: usize = 4;
+
+/// A type erased closure on the heap.
+struct Subscriber {
+    id: u32,
+    data: NonNull<u8>,
+    layout: Layout,
+    call: unsafe fn(NonNull<u8>, &WifiEvent),
+    drop: unsafe fn(NonNull<u8>),
+    /// The closure is being called by `publish`.
+    running: bool,
+}
+
+// the closures are required to be `Send`
+unsafe impl Send for Subscriber {}
+
+static SUBSCRIBERS: Mutex<RefCell<heapless::Vec<Subscriber, MAX_SUBSCRIBERS>>> =
+    Mutex::new(RefCell::new(heapless::Vec::new()));
+
+/// Subscribers unsubscribed while `publish` was calling them, `publish` frees them afterwards.
+static RETIRED: Mutex<RefCell<heapless::Vec<Subscriber, MAX_SUBSCRIBERS>>> =
+    Mutex::new(RefCell::new(heapless::Vec::new()));
+
+static NEXT_ID: Mutex<RefCell<u32>> = Mutex::new(RefCell::new(0));
+
+/// Pass `event` to all the subscribers.
+///
+/// The callbacks are called outside of the critical section, a subscriber which is still
+/// running from another context is skipped.
+pub(crate) fn publish(event: &WifiEvent) {
+    let ids: heapless::Vec<u32, MAX_SUBSCRIBERS> = critical_section::with(|cs| {
+        SUBSCRIBERS
+            .borrow_ref(cs)
+            .iter()
+            .map(|subscriber| subscriber.id)
+            .collect()
+    });
+
+    for id in ids {
+        let callback = critical_section::with(|cs| {
+            let mut subscribers = SUBSCRIBERS.borrow_ref_mut(cs);
+            let subscriber = subscribers
+                .iter_mut()
+                .find(|subscriber| subscriber.id == id && !subscriber.running)?;
+            subscriber.running = true;
+            Some((subscriber.call, subscriber.data))
+        });
+
+        let (call, data) = match callback {
+            Some(callback) => callback,
+            None => continue,
+        };
+
+        unsafe { call(data, event) };
+
+        let retired = critical_section::with(|cs| {
+            if let Some(subscriber) = SUBSCRIBERS
+                .borrow_ref_mut(cs)
+                .iter_mut()
+                .find(|subscriber| subscriber.id == id)
+            {
+                subscriber.running = false;
+                return None;
+            }
+
+            let mut retired = RETIRED.borrow_ref_mut(cs);
+            let index = retired.iter().position(|subscriber| subscriber.id == id)?;
+            Some(retired.swap_remove(index))
+        });
+
+        if let Some(subscriber) = retired {
+            unsafe { free(subscriber) };
+        }
+    }
+}
+
+/// A subscription to the `WifiEvent`s, dropping it unsubscribes.
+pub struct Subscription {
+    id: u32,
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        let subscriber = critical_section::with(|cs| {
+            let mut subscribers = SUBSCRIBERS.borrow_ref_mut(cs);
+            let index = subscribers
+                .iter()
+                .position(|subscriber| subscriber.id == self.id)?;
+            let subscriber = subscribers.swap_remove(index);
+
+            if subscriber.running {
+                // there is a slot for every subscriber, `push` can't fail
+                RETIRED.borrow_ref_mut(cs).push(subscriber).ok();
+                return None;
+            }
+
+            Some(subscriber)
+        });
+
+        if let Some(subscriber) = subscriber {
+            unsafe { free(subscriber) };
+        }
+    }
+}
+
+impl<'a> ErrorType for Wifi<'a> {
+    type Error = WifiError;
+}
+
+impl<'a> EventBus<WifiEvent> for Wifi<'a> {
+    type Subscription = Subscription;
+
+    /// Call `callback` on every `WifiEvent` until the returned `Subscription` is dropped.
+    ///
+    /// Up to four subscriptions can exist at the same time. Except for `GotIp` the callback
+    /// runs in the context of the Wi-Fi task, keep it short.
+    fn subscribe(
+        &mut self,
+        callback: impl for<'b> FnMut(&'b WifiEvent) + Send + 'static,
+    ) -> Result<Subscription, WifiError> {
+        let subscriber = allocate(callback).ok_or(WifiError::OutOfMemory)?;
+
+        critical_section::with(|cs| {
+            let mut next_id = NEXT_ID.borrow_ref_mut(cs);
+            let id = *next_id;
+
+            match SUBSCRIBERS
+                .borrow_ref_mut(cs)
+                .push(Subscriber { id, ..subscriber })
+            {
+                Ok(()) => {
+                    *next_id = id.wrapping_add(1);
+                    Ok(Subscription { id })
+                }
+                Err(subscriber) => Err(subscriber),
+            }
+        })
+        .map_err(|subscriber| {
+            unsafe { free(subscriber) };
+            WifiError::TooManySubscribers
+        })
+    }
+}
+
+/// Move `callback` to the heap.
+fn allocate<F>(callback: F) -> Option<Subscriber>
+where
+    F: FnMut(&WifiEvent) + Send + 'static,
+{
+    unsafe fn call<F: FnMut(&WifiEvent)>(data: NonNull<u8>, event: &WifiEvent) {
+        (*(data.as_ptr() as *mut F))(event)
+    }
+
+    unsafe fn drop<F>(data: NonNull<u8>) {
+        core::ptr::drop_in_place(data.as_ptr() as *mut F)
+    }
+
+    let layout = Layout::new::<F>();
+    let data = if layout.size() == 0 {
+        NonNull::<F>::dangling().cast()
+    } else {
+        critical_section::with(|cs| HEAP.borrow_ref_mut(cs).allocate_first_fit(layout).ok())?
+    };
+
+    unsafe { core::ptr::write(data.as_ptr() as *mut F, callback) };
+
+    Some(Subscriber {
+        id: 0,
+        data,
+        layout,
+        call: call::<F>,
+        drop: drop::<F>,
+        running: false,
+    })
+}
+
+/// Drop the closure of `subscriber` and give back its memory.
+unsafe fn free(subscriber: Subscriber) {
+    (subscriber.drop)(subscriber.data);
+
+    if subscriber.layout.size() != 0 {
+        critical_section::with(|cs| {
+            HEAP.borrow_ref_mut(cs)
+                .deallocate(subscriber.data, subscriber.layout)
+        });
+    }
+}
@@ -0,0 +1,498 @@
+//! A minimal mDNS (RFC 6762) responder.
+//!
+//! It answers queries for the A record of `<hostname>.local` and, if a service is registered,
+//! the DNS-SD (RFC 6763) records of `<hostname>.<service>.local`. It never sends queries itself
+//! and doesn't probe for conflicting names.
+
+use core::fmt::Write;
+
+use atomic_polyfill::{AtomicBool, Ordering};
+
+use smoltcp::socket::{UdpPacketMetadata, UdpSocket as SmolUdpSocket, UdpSocketBuffer};
+use smoltcp::wire::{IpAddress, IpEndpoint, Ipv4Address};
+
+use super::{BorrowedTcpSlot, Network, WifiError};
+
+pub const MDNS_PORT: u16 = 5353;
+pub const MDNS_GROUP: Ipv4Address = Ipv4Address([224, 0, 0, 251]);
+
+const HEADER_LEN: usize = 12;
+const FLAG_RESPONSE: u16 = 0x8000;
+const FLAG_AUTHORITATIVE: u16 = 0x0400;
+const TYPE_A: u16 = 1;
+const TYPE_PTR: u16 = 12;
+const TYPE_TXT: u16 = 16;
+const TYPE_SRV: u16 = 33;
+const TYPE_ANY: u16 = 255;
+const CLASS_IN: u16 = 1;
+const CLASS_CACHE_FLUSH: u16 = 0x8000;
+const TTL_HOST: u32 = 120;
+const TTL_OTHER: u32 = 4500;
+
+const MAX_NAME_LEN: usize = 255;
+const MAX_PACKET_LEN: usize = 512;
+
+type Name = heapless::String<MAX_NAME_LEN>;
+
+/// Set while a responder exists, it owns the static buffers of the socket.
+static IN_USE: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MdnsError {
+    /// The hostname or the service name is not valid
+    InvalidName,
+    /// A query couldn't be parsed
+    MalformedQuery,
+    /// Another responder exists already
+    AlreadyRunning,
+}
+
+/// A set of records, used to collect the answers to a query.
+#[derive(Default, Clone, Copy)]
+struct Records {
+    a: bool,
+    ptr: bool,
+    srv: bool,
+    txt: bool,
+}
+
+impl Records {
+    fn any(&self) -> bool {
+        self.a || self.ptr || self.srv || self.txt
+    }
+
+    fn count(&self) -> u16 {
+        self.a as u16 + self.ptr as u16 + self.srv as u16 + self.txt as u16
+    }
+}
+
+/// mDNS responder running on a `Network`.
+///
+/// The UDP socket takes the slot of a TCP socket which isn't in use until the responder
+/// is dropped. Only a single responder can exist at a time, it owns the buffers of the socket.
+pub struct Mdns<'s, 'n: 's> {
+    network: &'s Network<'n>,
+    slot: BorrowedTcpSlot<'s, 'n>,
+    hostname: heapless::String<32>,
+    service: Option<(heapless::String<32>, u16)>,
+}
+
+impl<'s, 'n: 's> Mdns<'s, 'n> {
+    /// Start answering queries for `<hostname>.local`.
+    ///
+    /// `hostname` is also used as the DHCP hostname (see `Wifi::set_hostname`) so both stay
+    /// the same, it has to satisfy the same rules. The interface needs an IPv4 address to join
+    /// the mDNS multicast group.
+    pub fn new(network: &'s Network<'n>, hostname: &str) -> Result<Mdns<'s, 'n>, WifiError> {
+        static mut UDP_RX_METADATA: [UdpPacketMetadata; 2] = [UdpPacketMetadata::EMPTY; 2];
+        static mut UDP_RX_DATA: [u8; 1024] = [0; 1024];
+        static mut UDP_TX_METADATA: [UdpPacketMetadata; 2] = [UdpPacketMetadata::EMPTY; 2];
+        static mut UDP_TX_DATA: [u8; 1024] = [0; 1024];
+
+        network.with_interface(|interface| interface.set_hostname(hostname))?;
+        let mut name = heapless::String::new();
+        // can't fail, `set_hostname` checked the length
+        name.push_str(hostname).ok();
+
+        if IN_USE.swap(true, Ordering::Acquire) {
+            return Err(MdnsError::AlreadyRunning.into());
+        }
+
+        let (rx_buffer, tx_buffer) = unsafe {
+            (
+                UdpSocketBuffer::new(&mut UDP_RX_METADATA[..], &mut UDP_RX_DATA[..]),
+                UdpSocketBuffer::new(&mut UDP_TX_METADATA[..], &mut UDP_TX_DATA[..]),
+            )
+        };
+        let mut udp_socket = SmolUdpSocket::new(rx_buffer, tx_buffer);
+        // can't fail, the port isn't 0 and the socket isn't bound yet
+        udp_socket.bind(MDNS_PORT).ok();
+
+        let slot = match network.borrow_tcp_slot(udp_socket) {
+            Some(slot) => slot,
+            None => {
+                IN_USE.store(false, Ordering::Release);
+                return Err(WifiError::NoSocket);
+            }
+        };
+
+        let mdns = Mdns {
+            network,
+            slot,
+            hostname: name,
+            service: None,
+        };
+
+        network.with_interface(|interface| interface.join_multicast_group(MDNS_GROUP))?;
+
+        Ok(mdns)
+    }
+
+    /// Announce a DNS-SD service, e.g. `_http._tcp`, on `port` as `<hostname>.<service>.local`.
+    ///
+    /// Only a single service is supported, a previously registered one is replaced.
+    pub fn set_service(&mut self, service: &str, port: u16) -> Result<(), WifiError> {
+        let valid = service.split('.').count() == 2
+            && service
+                .split('.')
+                .all(|label| label.len() > 1 && label.starts_with('_'));
+        if !valid {
+            return Err(MdnsError::InvalidName.into());
+        }
+
+        let mut name = heapless::String::new();
+        name.push_str(service)
+            .map_err(|_| WifiError::from(MdnsError::InvalidName))?;
+        self.service = Some((name, port));
+
+        Ok(())
+    }
+
+    /// Stop announcing the service set via `set_service`.
+    pub fn clear_service(&mut self) {
+        self.service = None;
+    }
+
+    /// Answer all the queries received since the last call.
+    ///
+    /// Call this regularly, e.g. in the same loop calling `Network::work`.
+    pub fn poll(&mut self) -> Result<(), WifiError> {
+        self.network.work();
+
+        let mut query = [0u8; MAX_PACKET_LEN];
+        let mut response = [0u8; MAX_PACKET_LEN];
+        loop {
+            let received = self.network.with_interface(|interface| {
+                interface
+                    .network_interface()
+                    .get_socket::<SmolUdpSocket>(self.slot.handle)
+                    .recv_slice(&mut query)
+                    .ok()
+            });
+
+            let (len, endpoint) = match received {
+                Some(received) => received,
+                None => break,
+            };
+
+            let address = match self.address() {
+                Some(address) => address,
+                None => continue,
+            };
+
+            // a malformed query shouldn't stop us from answering the other ones
+            let records = match self.parse_query(&query[..len]) {
+                Ok(records) if records.any() => records,
+                _ => continue,
+            };
+
+            // queries not from port 5353 are "legacy" unicast queries, answer them directly
+            let (id, destination) = if endpoint.port == MDNS_PORT {
+                (0, IpEndpoint::new(IpAddress::Ipv4(MDNS_GROUP), MDNS_PORT))
+            } else {
+                (u16::from_be_bytes([query[0], query[1]]), endpoint)
+            };
+
+            let response_len = match self.write_response(id, records, address, &mut response) {
+                Some(response_len) => response_len,
+                None => continue,
+            };
+
+            self.network.with_interface(|interface| {
+                interface
+                    .network_interface()
+                    .get_socket::<SmolUdpSocket>(self.slot.handle)
+                    .send_slice(&response[..response_len], destination)
+            })?;
+        }
+
+        self.network.work();
+        Ok(())
+    }
+
+    /// The IPv4 address announced in the A record.
+    fn address(&self) -> Option<Ipv4Address> {
+        self.network.with_interface(|interface| {
+            interface
+                .network_interface()
+                .ip_addrs()
+                .iter()
+                .find_map(|cidr| match cidr.address() {
+                    IpAddress::Ipv4(address) if !address.is_unspecified() => Some(address),
+                    _ => None,
+                })
+        })
+    }
+
+    fn host_name(&self) -> Name {
+        let mut name = Name::new();
+        write!(name, "{}.local", self.hostname).ok();
+        name
+    }
+
+    fn service_name(&self) -> Option<Name> {
+        let (service, _) = self.service.as_ref()?;
+        let mut name = Name::new();
+        write!(name, "{}.local", service).ok();
+        Some(name)
+    }
+
+    fn instance_name(&self) -> Option<Name> {
+        let (service, _) = self.service.as_ref()?;
+        let mut name = Name::new();
+        write!(name, "{}.{}.local", self.hostname, service).ok();
+        Some(name)
+    }
+
+    /// Collect the records answering the questions of `query`.
+    fn parse_query(&self, query: &[u8]) -> Result<Records, MdnsError> {
+        let mut records = Records::default();
+
+        if query.len() < HEADER_LEN || read_u16(query, 2)? & FLAG_RESPONSE != 0 {
+            return Ok(records);
+        }
+
+        let host_name = self.host_name();
+        let service_name = self.service_name();
+        let instance_name = self.instance_name();
+
+        let questions = read_u16(query, 4)?;
+        let mut pos = HEADER_LEN;
+        for _ in 0..questions {
+            let (name, next) = read_name(query, pos)?;
+            let qtype = read_u16(query, next)?;
+            pos = next + 4;
+
+            let is = |expected: &Option<Name>| {
+                expected
+                    .as_ref()
+                    .map_or(false, |expected| expected.eq_ignore_ascii_case(&name))
+            };
+            let any = qtype == TYPE_ANY;
+
+            if name.eq_ignore_ascii_case(&host_name) && (any || qtype == TYPE_A) {
+                records.a = true;
+            }
+            if is(&service_name) && (any || qtype == TYPE_PTR) {
+                records.ptr = true;
+            }
+            if is(&instance_name) && (any || qtype == TYPE_SRV) {
+                records.srv = true;
+            }
+            if is(&instance_name) && (any || qtype == TYPE_TXT) {
+                records.txt = true;
+            }
+        }
+
+        Ok(records)
+    }
+
+    /// Write a response containing `answers` into `buffer`.
+    ///
+    /// The records a resolver needs next (SRV, TXT and A for a PTR answer, A for an SRV answer)
+    /// are added as additional records. Returns `None` if the response doesn't fit.
+    fn write_response(
+        &self,
+        id: u16,
+        answers: Records,
+        address: Ipv4Address,
+        buffer: &mut [u8],
+    ) -> Option<usize> {
+        let additionals = Records {
+            a: !answers.a && (answers.ptr || answers.srv),
+            ptr: false,
+            srv: !answers.srv && answers.ptr,
+            txt: !answers.txt && answers.ptr,
+        };
+
+        buffer.get_mut(..HEADER_LEN)?.fill(0);
+        buffer[0..2].copy_from_slice(&id.to_be_bytes());
+        buffer[2..4].copy_from_slice(&(FLAG_RESPONSE | FLAG_AUTHORITATIVE).to_be_bytes());
+        buffer[6..8].copy_from_slice(&answers.count().to_be_bytes());
+        buffer[10..12].copy_from_slice(&additionals.count().to_be_bytes());
+
+        let mut pos = HEADER_LEN;
+        for records in [answers, additionals] {
+            pos = self.write_records(records, address, buffer, pos)?;
+        }
+
+        Some(pos)
+    }
+
+    fn write_records(
+        &self,
+        records: Records,
+        address: Ipv4Address,
+        buffer: &mut [u8],
+        mut pos: usize,
+    ) -> Option<usize> {
+        let host_name = self.host_name();
+        let port = self.service.as_ref().map_or(0, |(_, port)| *port);
+
+        if records.ptr {
+            let service_name = self.service_name()?;
+            let instance_name = self.instance_name()?;
+            pos = write_record_header(buffer, pos, &service_name, TYPE_PTR, CLASS_IN, TTL_OTHER)?;
+            pos = write_rdata(buffer, pos, |rdata| write_name(rdata, 0, &instance_name))?;
+        }
+
+        if records.srv {
+            let instance_name = self.instance_name()?;
+            pos = write_record_header(
+                buffer,
+                pos,
+                &instance_name,
+                TYPE_SRV,
+                CLASS_IN | CLASS_CACHE_FLUSH,
+                TTL_HOST,
+            )?;
+            pos = write_rdata(buffer, pos, |rdata| {
+                // priority and weight
+                rdata.get_mut(..4)?.fill(0);
+                rdata.get_mut(4..6)?.copy_from_slice(&port.to_be_bytes());
+                write_name(rdata, 6, &host_name)
+            })?;
+        }
+
+        if records.txt {
+            let instance_name = self.instance_name()?;
+            pos = write_record_header(
+                buffer,
+                pos,
+                &instance_name,
+                TYPE_TXT,
+                CLASS_IN | CLASS_CACHE_FLUSH,
+                TTL_OTHER,
+            )?;
+            // a single empty string, there is no TXT data
+            pos = write_rdata(buffer, pos, |rdata| {
+                *rdata.get_mut(0)? = 0;
+                Some(1)
+            })?;
+        }
+
+        if records.a {
+            pos = write_record_header(
+                buffer,
+                pos,
+                &host_name,
+                TYPE_A,
+                CLASS_IN | CLASS_CACHE_FLUSH,
+                TTL_HOST,
+            )?;
+            pos = write_rdata(buffer, pos, |rdata| {
+                rdata.get_mut(..4)?.copy_from_slice(address.as_bytes());
+                Some(4)
+            })?;
+        }
+
+        Some(pos)
+    }
+}
+
+impl<'s, 'n: 's> Drop for Mdns<'s, 'n> {
+    fn drop(&mut self) {
+        // the slot puts the TCP socket back afterwards
+        self.network
+            .try_with_interface(|interface| interface.leave_multicast_group(MDNS_GROUP).ok())
+            .ok();
+        IN_USE.store(false, Ordering::Release);
+    }
+}
+
+/// Read the (possibly compressed) name starting at `pos` as a dotted string.
+///
+/// Returns the name and the position after it.
+fn read_name(data: &[u8], mut pos: usize) -> Result<(Name, usize), MdnsError> {
+    let mut name = Name::new();
+    let mut end = None;
+    // bound the number of pointers followed, a loop of pointers would never end otherwise
+    let mut jumps = 0;
+
+    loop {
+        let len = *data.get(pos).ok_or(MdnsError::MalformedQuery)? as usize;
+        match len {
+            0 => break,
+            len if len & 0xc0 == 0xc0 => {
+                let low = *data.get(pos + 1).ok_or(MdnsError::MalformedQuery)? as usize;
+                end.get_or_insert(pos + 2);
+                pos = (len & 0x3f) << 8 | low;
+
+                jumps += 1;
+                if jumps > 16 {
+                    return Err(MdnsError::MalformedQuery);
+                }
+            }
+            len => {
+                let label = data
+                    .get(pos + 1..pos + 1 + len)
+                    .and_then(|label| core::str::from_utf8(label).ok())
+                    .ok_or(MdnsError::MalformedQuery)?;
+                if !name.is_empty() {
+                    name.push('.').map_err(|_| MdnsError::MalformedQuery)?;
+                }
+                name.push_str(label)
+                    .map_err(|_| MdnsError::MalformedQuery)?;
+                pos += 1 + len;
+            }
+        }
+    }
+
+    Ok((name, end.unwrap_or(pos + 1)))
+}
+
+/// Write `name` uncompressed at `pos`, returns the position after it.
+fn write_name(buffer: &mut [u8], mut pos: usize, name: &str) -> Option<usize> {
+    for label in name.split('.') {
+        *buffer.get_mut(pos)? = label.len() as u8;
+        buffer
+            .get_mut(pos + 1..pos + 1 + label.len())?
+            .copy_from_slice(label.as_bytes());
+        pos += 1 + label.len();
+    }
+    *buffer.get_mut(pos)? = 0;
+
+    Some(pos + 1)
+}
+
+/// Write the name, type, class and TTL of a record, returns the position of the RDLENGTH field.
+fn write_record_header(
+    buffer: &mut [u8],
+    pos: usize,
+    name: &str,
+    rtype: u16,
+    class: u16,
+    ttl: u32,
+) -> Option<usize> {
+    let pos = write_name(buffer, pos, name)?;
+    buffer
+        .get_mut(pos..pos + 2)?
+        .copy_from_slice(&rtype.to_be_bytes());
+    buffer
+        .get_mut(pos + 2..pos + 4)?
+        .copy_from_slice(&class.to_be_bytes());
+    buffer
+        .get_mut(pos + 4..pos + 8)?
+        .copy_from_slice(&ttl.to_be_bytes());
+
+    Some(pos + 8)
+}
+
+/// Let `f` write the RDATA after the RDLENGTH field at `pos` and fill in its length.
+fn write_rdata<F>(buffer: &mut [u8], pos: usize, f: F) -> Option<usize>
+where
+    F: FnOnce(&mut [u8]) -> Option<usize>,
+{
+    let len = f(buffer.get_mut(pos + 2..)?)?;
+    buffer
+        .get_mut(pos..pos + 2)?
+        .copy_from_slice(&(len as u16).to_be_bytes());
+
+    Some(pos + 2 + len)
+}
+
+fn read_u16(data: &[u8], pos: usize) -> Result<u16, MdnsError> {
+    data.get(pos..pos + 2)
+        .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+        .ok_or(MdnsError::MalformedQuery)
+}
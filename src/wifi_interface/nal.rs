@@ -0,0 +1,125 @@
+//! `embedded-nal` support for `Network`
+//!
+//! Only the `nb` based `TcpClientStack` is implemented. None of the calls block, they poll the
+//! interface once and return `nb::Error::WouldBlock` if the operation can't complete yet.
+
+use embedded_nal::{SocketAddr, TcpClientStack};
+use smoltcp::iface::SocketHandle;
+use smoltcp::socket::{TcpSocket as SmolTcpSocket, TcpState};
+use smoltcp::wire::Ipv4Address;
+
+use super::{IoError, Network};
+
+/// A TCP socket handed out by `TcpClientStack::socket`.
+pub struct TcpSocket {
+    socket_handle: SocketHandle,
+    connecting: bool,
+}
+
+impl<'a> TcpClientStack for Network<'a> {
+    type TcpSocket = TcpSocket;
+    type Error = IoError;
+
+    /// Get a TCP socket which isn't in use, the socket is shared with `Network::get_socket`.
+    fn socket(&mut self) -> Result<TcpSocket, IoError> {
+        let socket_handle = self.allocate_tcp_socket().ok_or(IoError::NoSocket)?;
+
+        Ok(TcpSocket {
+            socket_handle,
+            connecting: false,
+        })
+    }
+
+    fn connect(&mut self, socket: &mut TcpSocket, remote: SocketAddr) -> nb::Result<(), IoError> {
+        let remote = match remote {
+            SocketAddr::V4(remote) => (Ipv4Address(remote.ip().octets()), remote.port()),
+            SocketAddr::V6(_) => return Err(IoError::Other(smoltcp::Error::Unaddressable).into()),
+        };
+
+        if !socket.connecting {
+            let local_port = self.next_local_port();
+            self.with_interface(|interface| {
+                let (sock, cx) = interface
+                    .network_interface()
+                    .get_socket_and_context::<SmolTcpSocket>(socket.socket_handle);
+                sock.connect(cx, remote, local_port)
+            })
+            .map_err(IoError::Other)?;
+            socket.connecting = true;
+        }
+
+        self.work();
+
+        match self.tcp_state(socket) {
+            TcpState::Established => {
+                socket.connecting = false;
+                Ok(())
+            }
+            TcpState::SynSent | TcpState::SynReceived => Err(nb::Error::WouldBlock),
+            _ => {
+                socket.connecting = false;
+                Err(IoError::SocketClosed.into())
+            }
+        }
+    }
+
+    fn is_connected(&mut self, socket: &TcpSocket) -> Result<bool, IoError> {
+        Ok(self.tcp_state(socket) == TcpState::Established)
+    }
+
+    fn send(&mut self, socket: &mut TcpSocket, buffer: &[u8]) -> nb::Result<usize, IoError> {
+        self.work();
+
+        let res = self.with_interface(|interface| {
+            let sock = interface
+                .network_interface()
+                .get_socket::<SmolTcpSocket>(socket.socket_handle);
+
+            if !sock.may_send() {
+                return Err(IoError::SocketClosed.into());
+            }
+
+            if !sock.can_send() {
+                return Err(nb::Error::WouldBlock);
+            }
+
+            sock.send_slice(buffer)
+                .map_err(|e| nb::Error::Other(IoError::Other(e)))
+        })?;
+
+        self.work();
+        Ok(res)
+    }
+
+    /// Receive into `buffer`, `Ok(0)` is returned once the remote side closed the connection.
+    fn receive(&mut self, socket: &mut TcpSocket, buffer: &mut [u8]) -> nb::Result<usize, IoError> {
+        self.work();
+
+        self.tcp_receive(socket.socket_handle, buffer)
+    }
+
+    /// Close the socket, a connection which is still open is aborted.
+    fn close(&mut self, socket: TcpSocket) -> Result<(), IoError> {
+        self.with_interface(|interface| {
+            interface
+                .network_interface()
+                .get_socket::<SmolTcpSocket>(socket.socket_handle)
+                .abort()
+        });
+        self.release_socket(socket.socket_handle);
+
+        self.work();
+        Ok(())
+    }
+}
+
+impl<'a> Network<'a> {
+    fn tcp_state(&self, socket: &TcpSocket) -> TcpState {
+        self.with_interface(|interface| {
+            interface
+                .network_interface()
+                .get_socket::<SmolTcpSocket>(socket.socket_handle)
+                .state()
+        })
+    }
+}
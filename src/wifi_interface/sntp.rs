@@ -0,0 +1,254 @@
+//! A simple SNTP (RFC 4330) client to get the wall-clock time.
+//!
+//! The time is kept as the offset between the Unix time and `current_millis`, the uptime.
+//! Only the transmit timestamp of the server is used, which is good enough to within the
+//! round-trip time.
+
+use core::cell::RefCell;
+
+use critical_section::Mutex;
+use smoltcp::iface::SocketHandle;
+use smoltcp::socket::UdpSocket as SmolUdpSocket;
+use smoltcp::wire::{IpAddress, IpEndpoint, Ipv4Address};
+
+use super::{Network, WifiError};
+
+pub const NTP_PORT: u16 = 123;
+
+/// The server used by `Sntp::default_server`.
+pub const DEFAULT_SERVER: &str = "pool.ntp.org";
+
+const PACKET_LEN: usize = 48;
+const VERSION: u8 = 4;
+const MODE_CLIENT: u8 = 3;
+const MODE_SERVER: u8 = 4;
+
+/// Seconds between the NTP epoch (1900) and the Unix epoch (1970).
+const NTP_UNIX_OFFSET: u64 = 2_208_988_800;
+
+/// Resync once an hour by default.
+const DEFAULT_INTERVAL_MS: u64 = 60 * 60 * 1000;
+const DEFAULT_TIMEOUT_MS: u64 = 2000;
+
+/// The offset of the last sync of any `Sntp`, see `unix_time`.
+static UNIX_OFFSET_MS: Mutex<RefCell<Option<i64>>> = Mutex::new(RefCell::new(None));
+
+/// The current Unix time in seconds as of the last sync of any `Sntp`, or `None` if the time
+/// was never synced.
+pub fn unix_time() -> Option<u64> {
+    let offset_ms = critical_section::with(|cs| *UNIX_OFFSET_MS.borrow_ref(cs))?;
+    Some((crate::current_millis() as i64 + offset_ms) as u64 / 1000)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SntpError {
+    /// The response couldn't be parsed
+    MalformedResponse,
+    /// The server told us to go away (kiss-o'-death) or isn't synchronized itself
+    ServerUnavailable,
+    /// There was no answer in time
+    Timeout,
+    /// There is no unbound UDP socket to send the request from
+    NoSocket,
+}
+
+/// SNTP client using a server of a `Network`.
+pub struct Sntp<'s, 'n: 's> {
+    network: &'s Network<'n>,
+    server: Ipv4Address,
+    interval_ms: u64,
+    timeout_ms: u64,
+    last_sync_at: Option<u64>,
+    offset_ms: i64,
+}
+
+impl<'s, 'n: 's> Sntp<'s, 'n> {
+    /// Create a client for `server`, either a hostname or an IPv4 address.
+    ///
+    /// A hostname is resolved once via `Network::resolve`, for a pool create a new client
+    /// to get a different server. No request is sent before `sync` or `poll` is called.
+    pub fn new(network: &'s Network<'n>, server: &str) -> Result<Sntp<'s, 'n>, WifiError> {
+        let server = match server.parse::<embedded_svc::ipv4::Ipv4Addr>() {
+            Ok(address) => address,
+            Err(_) => network.resolve(server)?,
+        };
+
+        Ok(Sntp {
+            network,
+            server: Ipv4Address::from_bytes(&server.octets()),
+            interval_ms: DEFAULT_INTERVAL_MS,
+            timeout_ms: DEFAULT_TIMEOUT_MS,
+            last_sync_at: None,
+            offset_ms: 0,
+        })
+    }
+
+    /// Create a client for `DEFAULT_SERVER`.
+    pub fn default_server(network: &'s Network<'n>) -> Result<Sntp<'s, 'n>, WifiError> {
+        Self::new(network, DEFAULT_SERVER)
+    }
+
+    /// Set how often `poll` resyncs the time.
+    pub fn set_interval(&mut self, interval_ms: u64) {
+        self.interval_ms = interval_ms;
+    }
+
+    /// Set how long to wait for the answer of the server.
+    pub fn set_timeout(&mut self, timeout_ms: u64) {
+        self.timeout_ms = timeout_ms;
+    }
+
+    /// Request the time from the server and wait for the answer.
+    ///
+    /// Returns the current Unix time in seconds.
+    pub fn sync(&mut self) -> Result<u64, WifiError> {
+        let handle = self
+            .network
+            .allocate_udp_socket()
+            .ok_or(SntpError::NoSocket)?;
+
+        let local_port = self.network.next_local_port();
+        let res = self
+            .network
+            .with_interface(|interface| {
+                interface
+                    .network_interface()
+                    .get_socket::<SmolUdpSocket>(handle)
+                    .bind(local_port)
+            })
+            .map_err(WifiError::from)
+            .and_then(|_| self.request(handle));
+
+        self.network.release_udp_socket(handle);
+
+        let (sent_at, received_at, unix_ms) = res?;
+
+        // assume the server sent its timestamp half way through the round-trip
+        let local_ms = sent_at + (received_at - sent_at) / 2;
+        self.offset_ms = unix_ms as i64 - local_ms as i64;
+        self.last_sync_at = Some(received_at);
+        critical_section::with(|cs| *UNIX_OFFSET_MS.borrow_ref_mut(cs) = Some(self.offset_ms));
+
+        Ok(self.now().unwrap_or_default())
+    }
+
+    /// Resync if the time was never synced or the interval set via `set_interval` has passed.
+    ///
+    /// Returns `true` if the time got synced.
+    pub fn poll(&mut self) -> Result<bool, WifiError> {
+        let now = (self.network.current_millis_fn)();
+        let due = match self.last_sync_at {
+            Some(last_sync_at) => now - last_sync_at >= self.interval_ms,
+            None => true,
+        };
+
+        if due {
+            self.sync()?;
+        }
+
+        Ok(due)
+    }
+
+    /// The current Unix time in seconds or `None` if the time was never synced.
+    pub fn now(&self) -> Option<u64> {
+        self.now_millis().map(|ms| ms / 1000)
+    }
+
+    /// The current Unix time in milliseconds or `None` if the time was never synced.
+    pub fn now_millis(&self) -> Option<u64> {
+        self.last_sync_at?;
+        Some(((self.network.current_millis_fn)() as i64 + self.offset_ms) as u64)
+    }
+
+    /// The uptime in milliseconds at which the time was synced last.
+    pub fn last_sync(&self) -> Option<u64> {
+        self.last_sync_at
+    }
+
+    /// The Unix time in milliseconds minus the uptime as of the last sync.
+    pub fn offset_ms(&self) -> Option<i64> {
+        self.last_sync_at.map(|_| self.offset_ms)
+    }
+
+    /// Send a request and wait for the answer.
+    ///
+    /// Returns the uptime when the request was sent and the answer received together with the
+    /// Unix time in milliseconds of the server.
+    fn request(&self, handle: SocketHandle) -> Result<(u64, u64, u64), WifiError> {
+        let endpoint = IpEndpoint::new(IpAddress::Ipv4(self.server), NTP_PORT);
+
+        // the server copies our transmit timestamp into its originate timestamp,
+        // it doesn't need to be a valid time so use it to match the answer to the request
+        let sent_at = (self.network.current_millis_fn)();
+        let cookie = unsafe { crate::common_adapter::random() } as u64 ^ sent_at;
+
+        let mut request = [0u8; PACKET_LEN];
+        request[0] = VERSION << 3 | MODE_CLIENT;
+        request[40..48].copy_from_slice(&cookie.to_be_bytes());
+
+        self.network.with_interface(|interface| {
+            interface
+                .network_interface()
+                .get_socket::<SmolUdpSocket>(handle)
+                .send_slice(&request, endpoint)
+        })?;
+
+        let mut response = [0u8; PACKET_LEN];
+        let deadline = sent_at + self.timeout_ms;
+        while (self.network.current_millis_fn)() < deadline {
+            self.network.work();
+
+            let received = self.network.with_interface(|interface| {
+                interface
+                    .network_interface()
+                    .get_socket::<SmolUdpSocket>(handle)
+                    .recv_slice(&mut response)
+                    .ok()
+            });
+
+            if let Some((len, _)) = received {
+                let received_at = (self.network.current_millis_fn)();
+                if let Some(unix_ms) = parse_response(cookie, &response[..len])? {
+                    return Ok((sent_at, received_at, unix_ms));
+                }
+            }
+        }
+
+        Err(SntpError::Timeout.into())
+    }
+}
+
+/// Parse the answer to the request with the transmit timestamp `cookie`.
+///
+/// Returns the transmit timestamp of the server as Unix time in milliseconds
+/// or `Ok(None)` if `data` isn't an answer to our request.
+fn parse_response(cookie: u64, data: &[u8]) -> Result<Option<u64>, SntpError> {
+    if data.len() < PACKET_LEN {
+        return Err(SntpError::MalformedResponse);
+    }
+
+    let originate = u64::from_be_bytes(data[24..32].try_into().unwrap());
+    if data[0] & 0x07 != MODE_SERVER || originate != cookie {
+        return Ok(None);
+    }
+
+    // leap indicator 3 means the server clock isn't synchronized, stratum 0 is a kiss-o'-death
+    let stratum = data[1];
+    if data[0] >> 6 == 3 || stratum == 0 {
+        return Err(SntpError::ServerUnavailable);
+    }
+
+    let seconds = u32::from_be_bytes(data[40..44].try_into().unwrap()) as u64;
+    let fraction = u32::from_be_bytes(data[44..48].try_into().unwrap()) as u64;
+
+    // the seconds wrap around in 2036, assume times before the Unix epoch belong to the next era
+    let seconds = if seconds < NTP_UNIX_OFFSET {
+        seconds + (1 << 32)
+    } else {
+        seconds
+    };
+
+    Ok(Some(
+        (seconds - NTP_UNIX_OFFSET) * 1000 + ((fraction * 1000) >> 32),
+    ))
+}
@@ -0,0 +1,157 @@
+//! TLS 1.3 client sockets on top of `Socket` via `embedded-tls`
+//!
+//! Only the `TLS_AES_128_GCM_SHA256` cipher suite is offered. Verifying the server needs the
+//! `tls-verify` feature which pulls in `webpki`.
+
+use embedded_io::blocking::{Read, Write};
+use embedded_io::Io;
+use embedded_tls::blocking::{
+    Aes128GcmSha256, Certificate, TlsClock, TlsConfig, TlsConnection, TlsContext, TlsError,
+};
+use rand_core::{CryptoRng, RngCore};
+
+use super::Socket;
+
+/// Maximum size of a certificate of the server which can be verified.
+const CERT_SIZE: usize = 4096;
+
+#[derive(Debug)]
+pub enum TlsSocketError {
+    /// The handshake failed, e.g. because the certificate of the server couldn't be verified
+    Handshake(TlsError),
+    /// Reading from or writing to the underlying socket failed
+    Io(embedded_io::ErrorKind),
+    /// The TLS connection failed after the handshake
+    Tls(TlsError),
+    /// A CA certificate was given but the `tls-verify` feature isn't enabled
+    VerifyUnsupported,
+}
+
+impl embedded_io::Error for TlsSocketError {
+    fn kind(&self) -> embedded_io::ErrorKind {
+        match self {
+            TlsSocketError::Io(kind) => *kind,
+            _ => embedded_io::ErrorKind::Other,
+        }
+    }
+}
+
+impl TlsSocketError {
+    fn handshake(error: TlsError) -> Self {
+        match error {
+            TlsError::Io(kind) => TlsSocketError::Io(kind),
+            error => TlsSocketError::Handshake(error),
+        }
+    }
+
+    fn tls(error: TlsError) -> Self {
+        match error {
+            TlsError::Io(kind) => TlsSocketError::Io(kind),
+            error => TlsSocketError::Tls(error),
+        }
+    }
+}
+
+/// A TLS connection over a connected `Socket`.
+pub struct TlsSocket<'s, 'n: 's, 'b> {
+    connection: TlsConnection<'b, Socket<'s, 'n>, Aes128GcmSha256>,
+}
+
+impl<'s, 'n: 's, 'b> TlsSocket<'s, 'n, 'b> {
+    /// Perform the TLS handshake with `server_name` over the already opened `socket`.
+    ///
+    /// `record_buffer` has to hold a complete TLS record, servers might send records of up to
+    /// 16 KiB. If `ca` (a DER encoded X.509 certificate) is given, the certificate of the server
+    /// has to be issued by it for `server_name`. Its validity period is only checked once the
+    /// time was synced via `Sntp`. Without `ca` the server isn't verified at all.
+    ///
+    /// `embedded-tls` verifies against a single trust anchor, so only one root certificate can
+    /// be given. Verifying needs the `tls-verify` feature, `TlsSocketError::VerifyUnsupported`
+    /// is returned without it.
+    pub fn connect(
+        socket: Socket<'s, 'n>,
+        server_name: &'b str,
+        ca: Option<&'b [u8]>,
+        record_buffer: &'b mut [u8],
+    ) -> Result<TlsSocket<'s, 'n, 'b>, TlsSocketError> {
+        let config = TlsConfig::new().with_server_name(server_name);
+        let config = match ca {
+            Some(_) if cfg!(not(feature = "tls-verify")) => {
+                return Err(TlsSocketError::VerifyUnsupported)
+            }
+            Some(ca) => config.with_ca(Certificate::X509(ca)),
+            None => config.verify_cert(false).verify_hostname(false),
+        };
+
+        let mut connection = TlsConnection::new(socket, record_buffer);
+        let mut rng = HardwareRng;
+        connection
+            .open::<HardwareRng, SntpClock, CERT_SIZE>(TlsContext::new(&config, &mut rng))
+            .map_err(TlsSocketError::handshake)?;
+
+        Ok(TlsSocket { connection })
+    }
+
+    /// Send a close_notify alert and give back the underlying socket.
+    ///
+    /// If sending the alert fails the socket is dropped, which aborts the connection.
+    pub fn close(self) -> Result<Socket<'s, 'n>, TlsSocketError> {
+        self.connection.close().map_err(TlsSocketError::tls)
+    }
+}
+
+impl<'s, 'n: 's, 'b> Io for TlsSocket<'s, 'n, 'b> {
+    type Error = TlsSocketError;
+}
+
+impl<'s, 'n: 's, 'b> Read for TlsSocket<'s, 'n, 'b> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        self.connection.read(buf).map_err(TlsSocketError::tls)
+    }
+}
+
+impl<'s, 'n: 's, 'b> Write for TlsSocket<'s, 'n, 'b> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        self.connection.write(buf).map_err(TlsSocketError::tls)
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        self.connection.flush().map_err(TlsSocketError::tls)
+    }
+}
+
+/// The hardware RNG, it produces true random numbers while the radio is enabled.
+struct HardwareRng;
+
+impl RngCore for HardwareRng {
+    fn next_u32(&mut self) -> u32 {
+        unsafe { crate::common_adapter::random() as u32 }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        (self.next_u32() as u64) << 32 | self.next_u32() as u64
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(4) {
+            let random = self.next_u32().to_le_bytes();
+            chunk.copy_from_slice(&random[..chunk.len()]);
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+impl CryptoRng for HardwareRng {}
+
+/// The time as synced via `Sntp`, used to check the validity period of certificates.
+struct SntpClock;
+
+impl TlsClock for SntpClock {
+    fn now() -> Option<u64> {
+        super::sntp::unix_time()
+    }
+}